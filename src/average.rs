@@ -0,0 +1,205 @@
+//! Average Reward
+//!
+//! The discounted value of a state is hard to read as money, since it
+//! depends on the discount rate. The average-reward criterion instead
+//! maximizes the long-run expected profit per day, the gain. Along with
+//! the gain, each state has a bias, its expected total difference from the
+//! gain, so for the optimal policy
+//!
+//! ```text
+//! gain + h(s) = max_a [ r(s, a) + sum_s' p(s' | s, a) h(s') ]
+//! ```
+//!
+//! The bias is only defined up to a constant, so it's pinned to zero at the
+//! state with no cars at either location.
+//!
+//! Relative value iteration sweeps undiscounted Bellman optimality backups
+//! and subtracts the reference state's new value from every state after
+//! each sweep, which keeps the values bounded. Each sweep only moves the
+//! values part way to the backup, h <- tau T h + (1 - tau) h, the
+//! aperiodicity transform, which gives every state a chance of staying put
+//! without changing the optimal policy or the gain. Without it, the values
+//! can cycle forever when the optimal policy visits states periodically,
+//! which can happen with bounded demand, e.g., binomial or empirical.
+//!
+//! The iteration converges when the MDP is unichain, e.g., when any number
+//! of cars can be rented or returned in a day, as with Poisson demand, so
+//! every lot can empty or fill from any state. Otherwise states can have
+//! different gains and the sweeps never settle, so they stop with an error
+//! after `MAX_SWEEPS`.
+
+use std::{error, fmt};
+use rayon::prelude::*;
+use crate::model::TransitionModel;
+use crate::policy::Policy;
+use crate::Convergence;
+
+
+/// Fraction of each backup that's applied to the values, tau
+const APERIODICITY: f64 = 0.5;
+/// Sweeps before relative value iteration gives up
+pub const MAX_SWEEPS: u32 = 100_000;
+
+
+/// Relative value iteration stopped before the changes in state values
+/// settled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NotConverged {
+    /// Span of the changes in the last sweep
+    pub span: f64,
+}
+
+impl fmt::Display for NotConverged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "relative value iteration didn't converge in {MAX_SWEEPS} sweeps, \
+            span {:.3e}; states may have different gains", self.span)
+    }
+}
+
+impl error::Error for NotConverged {}
+
+
+/// Optimal policy, bias, and gain under the average-reward criterion.
+pub struct AverageReward {
+    /// Optimal actions. State values are the bias, and action values are
+    /// r(s, a) - gain + sum_s' p(s' | s, a) h(s').
+    pub policy: Policy,
+    /// Long-run expected reward per day
+    pub gain: f64,
+}
+
+
+/// Find the average-reward optimal policy with relative value iteration.
+///
+/// Sweeps stop when the span of the changes in state values, i.e., the
+/// largest change minus the smallest, is less than `theta`. The gain is
+/// then within `theta / 2` of the midpoint of the changes, which is the
+/// reported gain. Returns an error if the span is still at least `theta`
+/// after `MAX_SWEEPS` sweeps. Ties keep the first valid action, as in
+/// `value_iteration`.
+pub fn relative_value_iteration(
+    model: &TransitionModel, theta: f64
+) -> Result<(AverageReward, Convergence), NotConverged> {
+    let mut h = vec![0.0; model.n_states()];
+    let mut conv = Convergence::default();
+    let gain = loop {
+        let backup: Vec<f64> = model.actions.par_iter()
+            .map(|ams| {
+                ams.iter()
+                    .map(|am| am.reward + am.expected_value(&h))
+                    .fold(f64::NEG_INFINITY, f64::max)
+            })
+            .collect();
+        let (min, max) = backup.iter().zip(&h)
+            .map(|(new, old)| new - old)
+            .fold((f64::INFINITY, f64::NEG_INFINITY),
+                |(min, max), d| (min.min(d), max.max(d)));
+        conv.sweeps += 1;
+        if max - min < theta {
+            break (max + min) / 2.0;
+        }
+        if conv.sweeps >= MAX_SWEEPS {
+            return Err(NotConverged { span: max - min });
+        }
+        let new_h: Vec<f64> = backup.iter().zip(&h)
+            .map(|(new, old)| APERIODICITY * new + (1.0 - APERIODICITY) * old)
+            .collect();
+        let reference = new_h[0];
+        h = new_h.into_iter().map(|v| v - reference).collect();
+    };
+    let mut policy = Policy::build_from_model(model);
+    for (i, ams) in model.actions.iter().enumerate() {
+        let s = model.state(i);
+        let mut best = (0, f64::NEG_INFINITY);
+        for am in ams {
+            let value = am.reward - gain + am.expected_value(&h);
+            policy.set_value(s.n1, s.n2, am.a, value);
+            if value > best.1 {
+                best = (am.a, value);
+            }
+        }
+        policy.set_action(s.n1, s.n2, best.0);
+    }
+    conv.improvements += 1;
+    Ok((AverageReward { policy, gain }, conv))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cars::RentalAgency;
+    use crate::model::ActionModel;
+    use crate::solver::StateIterator;
+    use approx::assert_abs_diff_eq;
+
+    fn agency() -> RentalAgency {
+        RentalAgency::new(5, 3.0, 1.0, 5, 1.0, 3.0, 2)
+    }
+
+    #[test]
+    fn bias_satisfies_average_reward_bellman_equation() {
+        // Arrange
+        let model = TransitionModel::build(&agency());
+        // Act
+        let (avg, conv) = relative_value_iteration(&model, 1e-9).unwrap();
+        // Assert
+        assert!(conv.sweeps > 1);
+        assert_abs_diff_eq!(avg.policy.get_state_value(0, 0), 0.0, epsilon = 1e-6);
+        let h = avg.policy.state_values();
+        for (i, ams) in model.actions.iter().enumerate() {
+            let s = model.state(i);
+            let best = ams.iter()
+                .map(|am| am.reward + am.expected_value(h))
+                .fold(f64::NEG_INFINITY, f64::max);
+            assert_abs_diff_eq!(avg.gain + h[i], best, epsilon = 1e-6);
+            let am = model.action(i, avg.policy.get_action(s.n1, s.n2)).unwrap();
+            assert_abs_diff_eq!(am.reward + am.expected_value(h), best, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn gain_is_limit_of_discounted_values() {
+        // Arrange
+        let mut agency = agency();
+        agency.g = 0.999;
+        let model = TransitionModel::build(&agency);
+        // Act
+        let (avg, _) = relative_value_iteration(&model, 1e-9).unwrap();
+        let (pi, _) = crate::value_iteration(&model, 1e-3);
+        // Assert
+        for s in StateIterator::new(5, 5) {
+            assert_abs_diff_eq!((1.0 - agency.g) * pi.get_state_value(s.n1, s.n2),
+                avg.gain, epsilon = 0.05);
+        }
+    }
+
+    /// Two states with one action each that go to the other state.
+    fn two_state_model(rewards: [f64; 2], next: [usize; 2]) -> TransitionModel {
+        let actions = rewards.iter().zip(next)
+            .map(|(&reward, i)| vec![ActionModel { a: 0, reward, factors: vec![vec![(i, 1.0)]] }])
+            .collect();
+        TransitionModel { max1: 0, max2: 1, max_move: 0, g: 1.0, actions }
+    }
+
+    #[test]
+    fn periodic_chain_converges() {
+        // Arrange
+        let model = two_state_model([1.0, 0.0], [1, 0]);
+        // Act
+        let (avg, _) = relative_value_iteration(&model, 1e-9).unwrap();
+        // Assert
+        assert_abs_diff_eq!(avg.gain, 0.5, epsilon = 1e-9);
+        assert_abs_diff_eq!(avg.policy.get_state_value(0, 1), -0.5, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn different_gains_stop_with_an_error() {
+        // Arrange
+        let model = two_state_model([1.0, 0.0], [0, 1]);
+        // Act
+        let result = relative_value_iteration(&model, 1e-9);
+        // Assert
+        assert_eq!(result.err(), Some(NotConverged { span: 1.0 }));
+    }
+}
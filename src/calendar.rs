@@ -0,0 +1,268 @@
+//! Periodic Demand
+//!
+//! Rental and return means often depend on the day of the week. A
+//! `Calendar` holds one `RentalAgency` for each phase of a periodic
+//! schedule, e.g., seven phases for the days of the week. The phase is part
+//! of the state, so the state is (phase, n1, n2), and the phase advances by
+//! one each day, wrapping back to the first phase after the last one. The
+//! optimal policy of this periodic MDP is stationary in the extended state,
+//! i.e., there is one action table for each phase.
+//!
+//! States are indexed phase-major, index = phase * n + i, where n is the
+//! number of (n1, n2) states and i is the state's `TransitionModel` index.
+
+use crate::cars::RentalAgency;
+use crate::config::{CarConfig, ConfigError};
+use crate::model::{ActionModel, TransitionModel};
+use crate::policy::Policy;
+use crate::Convergence;
+
+
+/// One rental agency for each phase of a periodic schedule.
+pub struct Calendar {
+    /// Agencies in schedule order. All have the same lots and costs.
+    pub phases: Vec<RentalAgency>,
+    /// Label for each phase
+    pub names: Vec<String>,
+}
+
+impl Calendar {
+    /// Create a calendar from configuration parameters.
+    ///
+    /// Each `[[schedule]]` table becomes a phase. A configuration without a
+    /// schedule has a single phase, which is the same as `RentalAgency`.
+    pub fn from_config(config: &CarConfig) -> Result<Calendar, ConfigError> {
+        config.validate()?;
+        let mut phases = Vec::new();
+        for distributions in config.schedule_distributions()? {
+            phases.push(RentalAgency::from_distributions(config, distributions)?);
+        }
+        let names = (0..phases.len())
+            .map(|k| config.schedule.get(k)
+                .and_then(|phase| phase.name.clone())
+                .unwrap_or_else(|| format!("Phase {}", k + 1)))
+            .collect();
+        Ok(Calendar { phases, names })
+    }
+
+    /// Number of phases in the schedule.
+    pub fn n_phases(&self) -> usize {
+        self.phases.len()
+    }
+}
+
+
+/// Precalculated rewards and transition probabilities for every phase.
+pub struct CalendarModel {
+    /// Models for each phase, with next state indexes within the phase
+    pub phases: Vec<TransitionModel>,
+    /// Discount rate
+    pub g: f64,
+    /// Models for each valid action. Indexes are state index, action order.
+    pub actions: Vec<Vec<ActionModel>>,
+}
+
+impl CalendarModel {
+    /// Calculate rewards and transition probabilities for every phase,
+    /// state, and valid action.
+    ///
+    /// Actions taken in one phase lead to states in the next phase, so
+    /// each action model's next state indexes are shifted to that phase.
+    pub fn build(calendar: &Calendar) -> CalendarModel {
+        let phases: Vec<TransitionModel> = calendar.phases.iter()
+            .map(TransitionModel::build)
+            .collect();
+        let n = phases[0].n_states();
+        let k_phases = phases.len();
+        let actions = phases.iter().enumerate()
+            .flat_map(|(k, model)| {
+                let offset = ((k + 1) % k_phases) * n;
+                model.actions.iter().map(move |ams| {
+                    ams.iter().map(|am| shift(am, offset)).collect()
+                })
+            })
+            .collect();
+        CalendarModel { g: phases[0].g, phases, actions }
+    }
+
+    /// Number of (n1, n2) states in each phase.
+    pub fn phase_states(&self) -> usize {
+        self.phases[0].n_states()
+    }
+
+    /// Number of states, over all phases.
+    pub fn n_states(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Expected reward plus the discounted expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn backup(&self, am: &ActionModel, v: &[f64]) -> f64 {
+        am.reward + self.g * am.expected_value(v)
+    }
+}
+
+
+/// Copy of an action model with next state indexes moved by `offset`.
+fn shift(am: &ActionModel, offset: usize) -> ActionModel {
+    let mut factors = am.factors.clone();
+    if let Some(first) = factors.first_mut() {
+        for (i, _) in first.iter_mut() {
+            *i += offset;
+        }
+    }
+    ActionModel { a: am.a, reward: am.reward, factors }
+}
+
+
+/// Action tables and state values for each phase.
+pub struct CalendarPolicy {
+    /// One policy per phase, in schedule order
+    pub phases: Vec<Policy>,
+}
+
+impl CalendarPolicy {
+    /// Action for a phase and state.
+    pub fn get_action(&self, phase: usize, n1: u8, n2: u8) -> i8 {
+        self.phases[phase].get_action(n1, n2)
+    }
+
+    /// Value of a phase and state.
+    pub fn get_state_value(&self, phase: usize, n1: u8, n2: u8) -> f64 {
+        self.phases[phase].get_state_value(n1, n2)
+    }
+
+    /// Show each phase's policy and state values in the terminal.
+    pub fn show(&self, calendar: &Calendar) {
+        for (pi, name) in self.phases.iter().zip(&calendar.names) {
+            println!("\n=== {name} Policy ===");
+            pi.show_policy();
+            println!("\n=== {name} State Values ===");
+            pi.show_values();
+        }
+    }
+}
+
+
+/// Find the optimal calendar policy with value iteration.
+///
+/// Ties keep the first valid action, as in `table_value_iteration`.
+pub fn calendar_value_iteration(
+    model: &CalendarModel, theta: f64
+) -> (CalendarPolicy, Convergence) {
+    let (best, values, conv) =
+        crate::table_value_iteration(&model.actions, model.g, theta);
+    let n = model.phase_states();
+    let phases = model.phases.iter().enumerate()
+        .map(|(k, phase)| {
+            let mut pi = Policy::build_from_model(phase);
+            for i in 0..n {
+                let s = phase.state(i);
+                let a = model.actions[k * n + i][best[k * n + i]].a;
+                pi.set_action(s.n1, s.n2, a);
+                pi.set_value(s.n1, s.n2, a, values[k * n + i]);
+            }
+            pi
+        })
+        .collect();
+    (CalendarPolicy { phases }, conv)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PhaseConfig;
+    use crate::solver::StateIterator;
+    use approx::assert_abs_diff_eq;
+
+    fn config() -> CarConfig {
+        let toml = "max1 = 5\nrent_mean1 = 1\nreturn_mean1 = 2\n\
+            max2 = 5\nrent_mean2 = 2\nreturn_mean2 = 1\nmax_move = 2\n\
+            gamma = 0.9\n";
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn single_phase_matches_stationary_agency() {
+        // Arrange
+        let cfg = config();
+        let calendar = Calendar::from_config(&cfg).unwrap();
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Act
+        let model = CalendarModel::build(&calendar);
+        let (cal_pi, _) = calendar_value_iteration(&model, 1e-6);
+        let (pi, _) = crate::value_iteration(&TransitionModel::build(&agency), 1e-6);
+        // Assert
+        assert_eq!(calendar.n_phases(), 1);
+        assert_eq!(calendar.names, vec!["Phase 1"]);
+        assert_eq!(cal_pi.phases[0].policy, pi.policy);
+        for s in StateIterator::new(5, 5) {
+            assert_abs_diff_eq!(cal_pi.get_state_value(0, s.n1, s.n2),
+                pi.get_state_value(s.n1, s.n2), epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn phases_lead_to_the_next_phase() {
+        // Arrange
+        let mut cfg = config();
+        cfg.schedule = vec![
+            PhaseConfig { name: Some(String::from("Weekday")), ..Default::default() },
+            PhaseConfig { rent_mean2: Some(4.0), ..Default::default() },
+            PhaseConfig::default(),
+        ];
+        let calendar = Calendar::from_config(&cfg).unwrap();
+        // Act
+        let model = CalendarModel::build(&calendar);
+        // Assert
+        let n = model.phase_states();
+        assert_eq!(model.n_states(), 3 * n);
+        assert_eq!(calendar.names, vec!["Weekday", "Phase 2", "Phase 3"]);
+        assert_eq!(calendar.phases[1].rent_mean2, 4.0);
+        for (i, ams) in model.actions.iter().enumerate() {
+            let next_phase = (i / n + 1) % 3;
+            for am in ams {
+                let total: f64 = am.next().iter()
+                    .inspect(|(j, _)| assert_eq!(j / n, next_phase))
+                    .map(|(_, p)| p)
+                    .sum();
+                assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn busy_phase_changes_the_policy() {
+        // Arrange
+        let mut cfg = config();
+        cfg.schedule = vec![
+            PhaseConfig::default(),
+            PhaseConfig {
+                rent_mean1: Some(4.0), return_mean2: Some(4.0), ..Default::default()
+            },
+        ];
+        let calendar = Calendar::from_config(&cfg).unwrap();
+        let model = CalendarModel::build(&calendar);
+        // Act
+        let (pi, conv) = calendar_value_iteration(&model, 1e-6);
+        // Assert
+        assert!(conv.sweeps > 1);
+        assert_ne!(pi.phases[0].policy, pi.phases[1].policy);
+        // Values satisfy the Bellman equation across the phase boundary.
+        let n = model.phase_states();
+        let values: Vec<f64> = pi.phases.iter()
+            .flat_map(|p| p.state_values().to_vec())
+            .collect();
+        for (k, phase) in model.phases.iter().enumerate() {
+            for i in 0..n {
+                let s = phase.state(i);
+                let a = pi.get_action(k, s.n1, s.n2);
+                let am = model.actions[k * n + i].iter().find(|am| am.a == a).unwrap();
+                assert_abs_diff_eq!(model.backup(am, &values), values[k * n + i],
+                    epsilon = 1e-4);
+            }
+        }
+    }
+}
@@ -1,12 +1,39 @@
 
 use std::{cmp, io};
-use statrs::distribution::{Discrete, DiscreteCDF, Poisson};
+use std::ops::RangeInclusive;
+use crate::config::{CarConfig, ConfigError};
+use crate::dist::{Distribution, JointDistribution};
 use crate::policy;
 use crate::solver::{State, Outcome, StateIterator};
 
 // use statrs::statistics::Data;
 
 
+/// Default revenue for each car rented
+pub const DEFAULT_RENTAL_PRICE: f64 = 10.0;
+/// Default cost for each car moved between locations
+pub const DEFAULT_MOVE_COST: f64 = 2.0;
+/// Default number of cars a location can keep overnight without a parking fee
+pub const DEFAULT_PARKING_LIMIT: u8 = 10;
+/// Default fee for keeping too many cars at a location overnight
+pub const DEFAULT_PARKING_FEE: f64 = 4.0;
+
+
+/// Rules for calculating the cost of an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardVariant {
+    /// Every car moved costs `move_cost`.
+    #[default]
+    Standard,
+    /// Sutton & Barto Exercise 4.7. An employee drives one car from location
+    /// #1 to location #2 each night for free, and a location that keeps more
+    /// than `parking_limit` cars overnight (after moving cars) pays
+    /// `parking_fee`.
+    Exercise4_7,
+}
+
+
 #[derive(Debug)]
 pub struct OutcomeProb {
     pub s1_n1: u8,
@@ -15,7 +42,7 @@ pub struct OutcomeProb {
     pub s2_n2: u8,
     pub xt: u32,
     pub a: i8,
-    pub r: i32,
+    pub r: f64,
     pub x1: i32,
     pub x2: i32,
     pub y1: i32,
@@ -25,7 +52,7 @@ pub struct OutcomeProb {
 
 impl OutcomeProb {
     pub fn new(
-        s1: &State, s2: &State, xt: u32, a: i8, r: i32, ocome: &Outcome, prob: f64
+        s1: &State, s2: &State, xt: u32, a: i8, r: f64, ocome: &Outcome, prob: f64
     ) -> OutcomeProb {
         OutcomeProb {
             s1_n1: s1.n1, s1_n2: s1.n2, s2_n1: s2.n1, s2_n2: s2.n2,
@@ -45,7 +72,21 @@ impl OutcomeProb {
 /// Precalculate rental and return probabilities when object is constructed.
 /// Indices to probability tables x1, y1, x2, and y2 are
 /// [cars on lot, number of cars rented or returned].
-/// Use Poisson distribution to calculate probabilities.
+/// Rentals and returns are Poisson unless other distributions are given
+/// with `with_distributions`. Returns are independent of rentals; returns
+/// from cars out on rent for several days are modeled by `OneWayModel`.
+/// 
+/// The two locations are independent once cars have been moved, so the
+/// next-morning probability tables t1 and t2, expected rentals e1 and e2,
+/// and expected unmet demand u1 and u2 are also precalculated for each
+/// location. Indices to t1 and t2 are [cars on lot after moving cars, cars
+/// on lot next morning].
+/// 
+/// If rental demand at the two locations is correlated (see
+/// `with_joint_rentals`), the locations aren't independent. The joint
+/// next-morning table `t_joint` then replaces t1 and t2 in the transition
+/// model. The expected rentals and unmet demand only depend on each
+/// location's own demand, so they are the same either way.
 pub struct RentalAgency {
     /// Maximum number of cars that can be stored at location #1
     pub max1: u8,
@@ -53,6 +94,10 @@ pub struct RentalAgency {
     pub rent_mean1: f32,
     /// Expected number of cars returned each day
     pub return_mean1: f32,
+    /// Loc 1 distribution of cars rented each day
+    pub rent_dist1: Distribution,
+    /// Loc 1 distribution of cars returned each day
+    pub return_dist1: Distribution,
     /// Loc 1 rental probs. Indexes: number of cars on lot, number of cars rented
     pub x1: ndarray::Array2<f64>,
     /// Loc 1 return probs. Indexes: number of cars on lot, number of cars returned
@@ -63,12 +108,50 @@ pub struct RentalAgency {
     pub rent_mean2: f32,
     /// Expected number of cars returned each day
     pub return_mean2: f32,
+    /// Loc 2 distribution of cars rented each day
+    pub rent_dist2: Distribution,
+    /// Loc 2 distribution of cars returned each day
+    pub return_dist2: Distribution,
     /// Loc 2 rental probs. Indexes: number of cars on lot, number of cars rented
     pub x2: ndarray::Array2<f64>, 
     /// Loc 2 return probs. Indexes: number of cars on lot, number of cars returned
     pub y2: ndarray::Array2<f64>,
+    /// Loc 1 next-morning probs. Indexes: cars on lot after moves, cars on lot next morning
+    pub t1: ndarray::Array2<f64>,
+    /// Loc 1 expected rentals. Index: cars on lot after moves
+    pub e1: ndarray::Array1<f64>,
+    /// Loc 2 next-morning probs. Indexes: cars on lot after moves, cars on lot next morning
+    pub t2: ndarray::Array2<f64>,
+    /// Loc 2 expected rentals. Index: cars on lot after moves
+    pub e2: ndarray::Array1<f64>,
+    /// Loc 1 expected customers turned away. Index: cars on lot after moves
+    pub u1: ndarray::Array1<f64>,
+    /// Loc 2 expected customers turned away. Index: cars on lot after moves
+    pub u2: ndarray::Array1<f64>,
+    /// Joint distribution of cars rented each day, if demand is correlated
+    pub rent_joint_dist: Option<JointDistribution>,
+    /// Joint demand probs if demand is correlated. Indexes: loc 1 demand,
+    /// loc 2 demand. The last index of each is that many cars or more.
+    pub rent_joint: Option<ndarray::Array2<f64>>,
+    /// Joint next-morning probs if demand is correlated. Indexes: state
+    /// index after moves, state index next morning
+    pub t_joint: Option<ndarray::Array2<f64>>,
     /// Maximum number of cars that can be moved between loc #1 and loc #2
     pub max_move: u8,
+    /// Revenue for each car rented at location #1
+    pub price1: f64,
+    /// Revenue for each car rented at location #2
+    pub price2: f64,
+    /// Cost for each car moved between locations
+    pub move_cost: f64,
+    /// Rules for calculating the cost of an action
+    pub variant: RewardVariant,
+    /// Cars a location can keep overnight without a parking fee
+    pub parking_limit: u8,
+    /// Fee for keeping more than `parking_limit` cars at a location overnight
+    pub parking_fee: f64,
+    /// Goodwill cost for each customer turned away because the lot is empty
+    pub lost_sale_penalty: f64,
     /// Discount rate
     pub g: f64,
 }
@@ -76,54 +159,155 @@ pub struct RentalAgency {
 impl RentalAgency {
 
     /// Create a new struct with pre-calculated probabilities.
+    /// 
+    /// Rentals and returns are Poisson with the given means.
     pub fn new(
         max1: u8, rent_mean1: f32, return_mean1: f32,
         max2: u8, rent_mean2: f32, return_mean2: f32,
         max_move: u8,
+    ) -> RentalAgency {
+        let poisson = |mean: f32| Distribution::poisson(f64::from(mean));
+        RentalAgency::with_distributions(
+            max1, poisson(rent_mean1), poisson(return_mean1),
+            max2, poisson(rent_mean2), poisson(return_mean2),
+            max_move)
+    }
+
+    /// Create a new struct with any rental and return distributions.
+    pub fn with_distributions(
+        max1: u8, rent_dist1: Distribution, return_dist1: Distribution,
+        max2: u8, rent_dist2: Distribution, return_dist2: Distribution,
+        max_move: u8,
     ) -> RentalAgency {
         if max_move > cmp::min(max1, max2) / 2 {
             panic!("Max move must be less than half of smallest lot max.")
         }
         let x1_probs =
-            RentalAgency::calc_rent_probs(rent_mean1, max1);
+            RentalAgency::calc_rent_probs(&rent_dist1, max1);
         let y1_probs = 
-            RentalAgency::calc_return_probs(return_mean1, max1);
+            RentalAgency::calc_return_probs(&return_dist1, max1);
         let x2_probs =
-            RentalAgency::calc_rent_probs(rent_mean2, max2);
+            RentalAgency::calc_rent_probs(&rent_dist2, max2);
         let y2_probs = 
-            RentalAgency::calc_return_probs(return_mean2, max2);
+            RentalAgency::calc_return_probs(&return_dist2, max2);
+
+        let (t1, e1) = RentalAgency::calc_next_probs(&x1_probs, &y1_probs);
+        let (t2, e2) = RentalAgency::calc_next_probs(&x2_probs, &y2_probs);
+        let u1 = RentalAgency::calc_unmet_demand(&rent_dist1, max1);
+        let u2 = RentalAgency::calc_unmet_demand(&rent_dist2, max2);
 
         RentalAgency {
-            max1, rent_mean1, return_mean1,
+            max1,
+            rent_mean1: rent_dist1.mean() as f32,
+            return_mean1: return_dist1.mean() as f32,
+            rent_dist1, return_dist1,
             x1: x1_probs, y1: y1_probs,
-            max2, rent_mean2, return_mean2,
+            max2,
+            rent_mean2: rent_dist2.mean() as f32,
+            return_mean2: return_dist2.mean() as f32,
+            rent_dist2, return_dist2,
             x2: x2_probs, y2: y2_probs,
+            t1, e1, t2, e2, u1, u2,
+            rent_joint_dist: None, rent_joint: None, t_joint: None,
             max_move,
+            price1: DEFAULT_RENTAL_PRICE,
+            price2: DEFAULT_RENTAL_PRICE,
+            move_cost: DEFAULT_MOVE_COST,
+            variant: RewardVariant::Standard,
+            parking_limit: DEFAULT_PARKING_LIMIT,
+            parking_fee: DEFAULT_PARKING_FEE,
+            lost_sale_penalty: 0.0,
             g: 0.9
         }
     }
 
-    /// Calculate the rental probabilities from the mean and max car limit.
+    /// Create a new struct with correlated rental demand.
     /// 
-    /// The mean is the mean number of cars that are rented each day.
-    /// Obviously you can't rent more cars than what's on the lot, so for
-    /// x = max_n, p(x) = 1 - P(x-1) where P is cumulative Poisson
-    /// distribution.
-    fn calc_rent_probs(mean: f32, max_n: u8) -> ndarray::Array2<f64> {
-        let rent_dist = Poisson::new(f64::from(mean)).unwrap();
+    /// The rental distribution at each location is the joint distribution's
+    /// marginal.
+    pub fn with_joint_rentals(
+        max1: u8, rent_joint: &JointDistribution, return_dist1: Distribution,
+        max2: u8, return_dist2: Distribution,
+        max_move: u8,
+    ) -> RentalAgency {
+        let [rent_dist1, rent_dist2] = rent_joint.marginals();
+        let mut agency = RentalAgency::with_distributions(
+            max1, rent_dist1, return_dist1,
+            max2, rent_dist2, return_dist2,
+            max_move);
+        agency.rent_joint_dist = Some(rent_joint.clone());
+        agency.rent_joint = Some(rent_joint.truncated(max1, max2));
+        agency.t_joint = Some(agency.calc_joint_next_probs());
+        agency
+    }
+
+    /// Create a new struct from configuration parameters.
+    /// 
+    /// Returns an error instead of calculating probabilities if any of the
+    /// parameters are invalid.
+    pub fn from_config(config: &CarConfig) -> Result<RentalAgency, ConfigError> {
+        config.validate()?;
+        RentalAgency::from_distributions(config, config.distributions()?)
+    }
+
+    /// Create a new struct from configuration parameters and the rental and
+    /// return distributions, [rent1, return1, rent2, return2].
+    /// 
+    /// Call `CarConfig::validate` first. The rental distributions are
+    /// ignored if the configuration has a joint rental table.
+    pub(crate) fn from_distributions(
+        config: &CarConfig, distributions: [Distribution; 4]
+    ) -> Result<RentalAgency, ConfigError> {
+        let [rent1, return1, rent2, return2] = distributions;
+        let mut agency = match config.joint_rentals()? {
+            Some(joint) => RentalAgency::with_joint_rentals(
+                config.max1, &joint, return1,
+                config.max2, return2,
+                config.max_move),
+            None => RentalAgency::with_distributions(
+                config.max1, rent1, return1,
+                config.max2, rent2, return2,
+                config.max_move),
+        };
+        agency.configure(config);
+        Ok(agency)
+    }
+
+    /// Copy discount rate, prices, and costs from configuration parameters.
+    fn configure(&mut self, config: &CarConfig) {
+        let agency = self;
+        agency.g = config.gamma;
+        agency.price1 = config.rental_price1.unwrap_or(config.rental_price);
+        agency.price2 = config.rental_price2.unwrap_or(config.rental_price);
+        agency.move_cost = config.move_cost;
+        agency.variant = config.reward_variant;
+        agency.parking_limit = config.parking_limit;
+        agency.parking_fee = config.parking_fee;
+        agency.lost_sale_penalty = config.lost_sale_penalty;
+    }
+
+    /// Calculate the rental probabilities from the distribution and max car
+    /// limit.
+    /// 
+    /// The distribution is the number of cars customers want to rent each
+    /// day. Obviously you can't rent more cars than what's on the lot, so for
+    /// x = n, p(x) = 1 - P(x-1) where P is the cumulative distribution.
+    pub(crate) fn calc_rent_probs(
+        rent_dist: &Distribution, max_n: u8
+    ) -> ndarray::Array2<f64> {
         let dim = (max_n + 1) as usize;
         let mut x_probs =
             ndarray::Array2::<f64>::zeros((dim, dim));
         for n in 0..max_n + 1 {
             for x in 0..max_n + 1 {
                 x_probs[[n as usize, x as usize]] = 
-                    RentalAgency::rent_prob(n, x, max_n, &rent_dist);
+                    RentalAgency::rent_prob(n, x, max_n, rent_dist);
             }
         }
         x_probs
     }
 
-    fn rent_prob(n: u8, x: u8, max_n: u8, rent_dist: &Poisson) -> f64 {
+    fn rent_prob(n: u8, x: u8, max_n: u8, rent_dist: &Distribution) -> f64 {
         // Can't fit more than max_n cars on lot.
         if n > max_n {
             return 0.0;
@@ -144,27 +328,29 @@ impl RentalAgency {
         0.0
     }
 
-    /// Calculate the return probabilities from the mean and max car limit.
+    /// Calculate the return probabilities from the distribution and max car
+    /// limit.
     /// 
-    /// The mean is the mean number of cars that are returned each day.
+    /// The distribution is the number of cars that are returned each day.
     /// Obviously you can't return more cars than what can fit on the lot, so
-    /// for y = max_n - n, p(y) = 1 - P(y-1) where P is cumulative Poisson
+    /// for y = max_n - n, p(y) = 1 - P(y-1) where P is the cumulative
     /// distribution.
-    fn calc_return_probs(mean: f32, max_n: u8) -> ndarray::Array2<f64> {
-        let return_dist = Poisson::new(f64::from(mean)).unwrap();
+    pub(crate) fn calc_return_probs(
+        return_dist: &Distribution, max_n: u8
+    ) -> ndarray::Array2<f64> {
         let dim = (max_n + 1) as usize;
         let mut y_probs =
             ndarray::Array2::<f64>::zeros((dim, dim));
         for n in 0..max_n + 1 {
             for y in 0..max_n + 1 {
                 y_probs[[n as usize, y as usize]] = 
-                    RentalAgency::return_prob(n, y, max_n, &return_dist);
+                    RentalAgency::return_prob(n, y, max_n, return_dist);
             }
         }
         y_probs
     }
 
-    fn return_prob(n :u8, y: u8, max_n: u8, return_dist: &Poisson) -> f64 {
+    fn return_prob(n :u8, y: u8, max_n: u8, return_dist: &Distribution) -> f64 {
         // Can't fit more than max_n cars on lot.
         if n > max_n {
             return 0.0;
@@ -192,19 +378,117 @@ impl RentalAgency {
         0.0
     }
 
+    /// Calculate a location's next-morning probabilities and expected rentals.
+    /// 
+    /// Combines the rental and return probability tables for one location.
+    /// Returns a table of probabilities indexed by [cars on lot after moving
+    /// cars, cars on lot next morning] and the expected number of cars
+    /// rented for each number of cars on the lot after moving cars.
+    pub(crate) fn calc_next_probs(
+        x_probs: &ndarray::Array2<f64>, y_probs: &ndarray::Array2<f64>
+    ) -> (ndarray::Array2<f64>, ndarray::Array1<f64>) {
+        let dim = x_probs.dim().0;
+        let mut t_probs = ndarray::Array2::<f64>::zeros((dim, dim));
+        let mut e_rented = ndarray::Array1::<f64>::zeros(dim);
+        for n in 0..dim {
+            for x in 0..=n {
+                let p_x = x_probs[[n, x]];
+                if p_x == 0.0 {
+                    continue;
+                }
+                e_rented[n] += p_x * x as f64;
+                for y in 0..(dim - n + x) {
+                    t_probs[[n, n - x + y]] += p_x * y_probs[[n - x, y]];
+                }
+            }
+        }
+        (t_probs, e_rented)
+    }
+
+    /// Calculate the probability of renting x1 and x2 cars with n1 and n2
+    /// cars on the lots, when demand is correlated.
+    /// 
+    /// As with `rent_prob`, renting every car on a lot lumps together all
+    /// demand for that many cars or more. Panics without a joint table.
+    pub fn joint_rent_prob(&self, n1: u8, n2: u8, x1: u8, x2: u8) -> f64 {
+        let joint = self.rent_joint.as_ref().expect("Demand is not correlated.");
+        let demand = |n: u8, x: u8, max: u8| {
+            if x < n { x as usize..x as usize + 1 } else { n as usize..max as usize + 1 }
+        };
+        if x1 > n1 || x2 > n2 {
+            return 0.0;
+        }
+        joint.slice(ndarray::s![demand(n1, x1, self.max1), demand(n2, x2, self.max2)])
+            .sum()
+    }
+
+    /// Calculate the joint next-morning probabilities for correlated demand.
+    /// 
+    /// Returns a table of probabilities indexed by [state index after moving
+    /// cars, state index next morning], with states in `StateIterator`
+    /// order. Returns are still independent.
+    fn calc_joint_next_probs(&self) -> ndarray::Array2<f64> {
+        let width = self.max2 as usize + 1;
+        let dim = (self.max1 as usize + 1) * width;
+        let mut t_probs = ndarray::Array2::<f64>::zeros((dim, dim));
+        for m in StateIterator::new(self.max1, self.max2) {
+            let row = m.n1 as usize * width + m.n2 as usize;
+            for x1 in 0..=m.n1 {
+                for x2 in 0..=m.n2 {
+                    let p_x = self.joint_rent_prob(m.n1, m.n2, x1, x2);
+                    if p_x == 0.0 {
+                        continue;
+                    }
+                    let (r1, r2) = ((m.n1 - x1) as usize, (m.n2 - x2) as usize);
+                    for y1 in 0..=self.max1 as usize - r1 {
+                        let p_y1 = self.y1[[r1, y1]];
+                        for y2 in 0..=self.max2 as usize - r2 {
+                            t_probs[[row, (r1 + y1) * width + r2 + y2]] +=
+                                p_x * p_y1 * self.y2[[r2, y2]];
+                        }
+                    }
+                }
+            }
+        }
+        t_probs
+    }
+
+    /// Calculate the expected number of customers turned away.
+    /// 
+    /// `rent_prob` lumps all demand of n or more cars into renting all n
+    /// cars on the lot. The unmet demand is the demand distribution's tail,
+    /// E[max(D - n, 0)] = mean - n + sum_(d < n) (n - d) p(d).
+    /// Returns the expected unmet demand for each number of cars on the lot
+    /// after moving cars.
+    pub(crate) fn calc_unmet_demand(
+        rent_dist: &Distribution, max_n: u8
+    ) -> ndarray::Array1<f64> {
+        let mean = rent_dist.mean();
+        ndarray::Array1::from_shape_fn(max_n as usize + 1, |n| {
+            let below: f64 = (0..n)
+                .map(|d| (n - d) as f64 * rent_dist.pmf(d as u64))
+                .sum();
+            // Clamp rounding errors when the tail is negligible.
+            (mean - n as f64 + below).max(0.0)
+        })
+    }
+
     /// Calculate the probability for set of rental and return totals.
     /// 
     /// Assumes that site #1 and site #2 rental and return probabilities are
-    /// indepdendent. Probabilities depend on number of care rented or returned
-    /// and the number of cars on the lot.
+    /// indepdendent, unless rental demand is correlated. Probabilities depend
+    /// on number of care rented or returned and the number of cars on the lot.
     pub fn outcome_prob(&self, s: &State, a: i8, outcome: &Outcome) -> f64 {
         let n1 = (s.n1 as i8 - a) as usize;
-        let p_x1 = self.x1[[n1, outcome.x1 as usize]];
         let p_y1 = self.y1[[n1 - outcome.x1 as usize, outcome.y1 as usize]];
         let n2 = (s.n2 as i8 + a) as usize;
-        let p_x2 = self.x2[[n2, outcome.x2 as usize]];
         let p_y2 = self.y2[[n2 - outcome.x2 as usize,outcome.y2 as usize]];
-        p_x1 * p_y1 * p_x2 * p_y2
+        let p_x = match self.rent_joint {
+            Some(_) => self.joint_rent_prob(
+                n1 as u8, n2 as u8, outcome.x1 as u8, outcome.x2 as u8),
+            None => self.x1[[n1, outcome.x1 as usize]] * self.x2[[n2, outcome.x2 as usize]],
+        };
+        p_x * p_y1 * p_y2
     }
 
     /// Display a probability table on the command line, for troubleshooting.
@@ -214,14 +498,12 @@ impl RentalAgency {
             print!("{:9}", n);
         }
         println!();
-        let mut x = 0;
-        for col in arr.columns() {
+        for (x, col) in arr.columns().into_iter().enumerate() {
             print!("{row_prefix}: {x:>3} | ");
-            x += 1;
             for elem in col.iter() {
                 print!("{:8.4} ", elem);
             }
-            println!("");
+            println!();
         }
     }
 
@@ -249,31 +531,136 @@ impl RentalAgency {
 
     }
 
-    /// Calculate number of cars rented from the reward and action.
-    pub fn cars_rented(r: i16, a: i16) -> u8 {
-        if !((r + 2 * a.abs()) % 10 == 0) {
+    /// Calculate number of cars rented from the reward, state, and action.
+    /// 
+    /// Only possible if both locations charge the same price and there is
+    /// no lost-sales penalty.
+    pub fn cars_rented(&self, s: &State, r: f64, a: i8) -> u32 {
+        if self.price1 != self.price2 {
+            panic!("Can't calculate cars rented when locations have different prices.")
+        }
+        if self.lost_sale_penalty != 0.0 {
+            panic!("Can't calculate cars rented with a lost-sales penalty.")
+        }
+        let cars = (r + self.action_cost(s, a)) / self.price1;
+        if cars < -1e-9 || (cars - cars.round()).abs() > 1e-9 {
             panic!("Invalid reward for given action.")
         }
-        let cars = (r + 2 * a.abs()) / 10;
-        if cars > u8::MAX as i16 {
-            panic!("Number of cars rented ({}) exceeds maximum.", cars)
+        cars.round() as u32
+    }
+
+    /// Calculate the cost of moving cars and keeping them overnight.
+    pub fn action_cost(&self, s: &State, a: i8) -> f64 {
+        self.move_and_parking_cost(a, s.n1 as i8 - a, s.n2 as i8 + a)
+    }
+
+    /// Calculate the cost of moving cars, given the number of cars kept
+    /// overnight at each location after moving cars.
+    fn move_and_parking_cost(&self, a: i8, parked1: i8, parked2: i8) -> f64 {
+        match self.variant {
+            RewardVariant::Standard => self.move_cost * a.abs() as f64,
+            RewardVariant::Exercise4_7 => {
+                let paid_moves = if a > 0 { a - 1 } else { -a };
+                let mut cost = self.move_cost * paid_moves as f64;
+                if parked1 as i16 > self.parking_limit as i16 {
+                    cost += self.parking_fee;
+                }
+                if parked2 as i16 > self.parking_limit as i16 {
+                    cost += self.parking_fee;
+                }
+                cost
+            }
         }
-        cars as u8
     }
 
-    /// Calculate the reward given the number of cars rented and action.
-    pub fn reward(xt: u32, a: i8) -> i32 {
-        xt as i32  * 10 - 2 * a.abs() as i32
+    /// Calculate the expected number of customers turned away at both
+    /// locations, given the state and action.
+    pub fn expected_unmet_demand(&self, s: &State, a: i8) -> f64 {
+        let n1 = (s.n1 as i8 - a) as usize;
+        let n2 = (s.n2 as i8 + a) as usize;
+        self.u1[n1] + self.u2[n2]
+    }
+
+    /// Calculate the reward given the state, the number of cars rented at
+    /// each location, and the action.
+    /// 
+    /// Customers are only turned away when every car on the lot is rented,
+    /// so the lost-sales penalty is the expected unmet demand given that
+    /// demand was at least the number of cars on the lot. If renting every
+    /// car has zero probability, e.g., a big lot with small mean demand, no
+    /// one is turned away.
+    pub fn reward(&self, s: &State, x1: u32, x2: u32, a: i8) -> f64 {
+        let mut r = x1 as f64 * self.price1 + x2 as f64 * self.price2
+            - self.action_cost(s, a);
+        if self.lost_sale_penalty != 0.0 {
+            let n1 = (s.n1 as i8 - a) as usize;
+            let n2 = (s.n2 as i8 + a) as usize;
+            let lost = [
+                (n1, x1 as usize, &self.x1, &self.u1),
+                (n2, x2 as usize, &self.x2, &self.u2),
+            ];
+            for (n, x, x_probs, u) in lost {
+                if x == n && x_probs[[n, n]] > 0.0 {
+                    r -= self.lost_sale_penalty * u[n] / x_probs[[n, n]];
+                }
+            }
+        }
+        r
+    }
+
+    /// Calculate the expected reward given the state and action.
+    /// 
+    /// The reward is linear in the number of cars rented and the number of
+    /// customers turned away, so it only depends on the expected rentals
+    /// and unmet demand at each location.
+    pub fn expected_reward(&self, s: &State, a: i8) -> f64 {
+        let n1 = (s.n1 as i8 - a) as usize;
+        let n2 = (s.n2 as i8 + a) as usize;
+        self.expected_rental_reward(n1, n2) - self.action_cost(s, a)
+    }
+
+    /// Calculate the expected reward when moved cars are in transit
+    /// tomorrow.
+    ///
+    /// Moved cars leave the sending lot tonight but aren't on the receiving
+    /// lot for tomorrow's rentals, and aren't parked at either location.
+    pub fn expected_transit_reward(&self, s: &State, a: i8) -> f64 {
+        let n1 = s.n1 as i8 - a.max(0);
+        let n2 = s.n2 as i8 + a.min(0);
+        self.expected_rental_reward(n1 as usize, n2 as usize)
+            - self.move_and_parking_cost(a, n1, n2)
+    }
+
+    /// Expected rental revenue less the lost-sales penalty, given the
+    /// number of cars on each lot after moving cars.
+    fn expected_rental_reward(&self, n1: usize, n2: usize) -> f64 {
+        self.e1[n1] * self.price1 + self.e2[n2] * self.price2
+            - self.lost_sale_penalty * (self.u1[n1] + self.u2[n2])
+    }
+
+    /// Range of valid actions for a state.
+    ///
+    /// Can't move more cars than what's on the sending lot, or more cars
+    /// than what will fit on the receiving lot.
+    pub fn actions(&self, s: &State) -> RangeInclusive<i8> {
+        let min_move = -(cmp::min(
+            cmp::min(self.max_move, s.n2),
+            self.max1 - s.n1
+        ) as i8);
+        let max_move = cmp::min(
+            cmp::min(self.max_move, s.n1),
+            self.max2 - s.n2) as i8;
+        min_move..=max_move
     }
 
     /// Calculate value for a given state, assume action is per current policy.
     ///
     /// The state is the number of cars at site #1 and site #2 at the beginning
     /// of the turn. The value is the discounted, expected total reward.
-    // pub fn calc_value(&self, s1: &State) -> f64 {
-    //     let a = self.pi.policy[[s1.n1 as usize, s1.n2 as usize]];
-    //     self.calc_value_for_action(s1, a)
-    // }
+    pub fn calc_value(&self, s1: &State, pi: &policy::Policy) -> f64 {
+        let a = pi.get_action(s1.n1, s1.n2);
+        self.calc_value_for_action(s1, a, pi)
+    }
 
     /// Calculate the value for a given state and action.
     /// 
@@ -282,46 +669,55 @@ impl RentalAgency {
     /// Iterate over all possible states and rewards. Calculate the probability
     /// of each state-reward combination and multiply it times the sum of the
     /// expected reward and the discounted values of the next state (s2).
+    /// The value of s2 is the policy's state value, V(s2).
     pub fn calc_value_for_action(
         &self, s1: &State, a: i8, pi: &policy::Policy) -> f64 {
         // Action is invalid if there are not enough cars to move or move exceeds max
-        if a > 0 {
-            if a + s1.n2 as i8 > self.max2 as i8 || s1.n1 as i8 - a < 0 {
-                return 0.0;
-            }
-        } else if a < 0 {
-            if s1.n1 as i8 - a > self.max1 as i8 || s1.n2 as i8 + a < 0 {
-                return 0.0;
-            } 
+        if !self.actions(s1).contains(&a) {
+            return 0.0;
         }
         let mut value = 0.0;
         for s2 in StateIterator::new(self.max1, self.max2) {
-            let mut v_s2 = pi.get_value(s2.n1, s2.n2, a);
+            let v_s2 = pi.get_state_value(s2.n1, s2.n2);
             let max_rented = s1.n1.checked_add(s1.n2)
                 .expect("Overflow") as u32;
             for xt in 0..(max_rented + 1) {
                 let (r, reward_prob, _) = self.calc_reward_prob(s1, &s2, a, xt);
-                value += reward_prob * (r as f64 + self.g * v_s2);
+                value += reward_prob * (r + self.g * v_s2);
             }
         }
         value
     }
 
-    /// Calculate probability of state s2 with reward r, given state s1 and action a.
+    /// Calculate probability of state s2 with xt total rentals, given state s1
+    /// and action a.
+    /// 
+    /// The reward for each outcome depends on where the cars were rented,
+    /// so the returned reward is the expected reward given s2 and xt.
     pub fn calc_reward_prob(
         &self, s1: &State, s2: &State, a: i8, xt: u32
-    ) -> (i32, f64, Vec<OutcomeProb>)  {
-        let r = RentalAgency::reward(xt, a);
-        let outcomes = Outcome::solve(s1, &s2, xt, a);
+    ) -> (f64, f64, Vec<OutcomeProb>)  {
+        let outcomes = Outcome::solve(s1, s2, xt, a);
         let mut reward_prob = 0.0;
+        let mut weighted_reward = 0.0;
         let mut oprobs: Vec<OutcomeProb> = Vec::new();
         for outcome in outcomes {
-            let prob = self.outcome_prob(&s1, a, &outcome);
+            let prob = self.outcome_prob(s1, a, &outcome);
+            if prob == 0.0 {
+                continue;
+            }
+            let r = self.reward(s1, outcome.x1 as u32, outcome.x2 as u32, a);
             oprobs.push(
                 OutcomeProb::new(s1, s2, xt, a, r, &outcome, prob)
             );
             reward_prob += prob;
+            weighted_reward += prob * r;
         }
+        let r = if reward_prob > 0.0 {
+            weighted_reward / reward_prob
+        } else {
+            self.reward(s1, xt, 0, a)
+        };
         (r, reward_prob, oprobs)
     }    
 
@@ -369,15 +765,63 @@ mod tests {
     #[test_case(5, -2, 46; "Rentals and action from 2 to 1")]
     #[test_case(4, 3, 34; "Rentals and action from 1 to 2")]
     fn test_reward_calculation(xt: u32, a: i8, r: i32) {
-        assert_eq!(RentalAgency::reward(xt, a), r);
+        let cprobs = RentalAgency::new(5, 1.0, 1.0, 5, 1.0, 1.0, 2);
+        let s = State { n1: 2, n2: 2 };
+        assert_eq!(cprobs.reward(&s, xt, 0, a), r as f64);
+        assert_eq!(cprobs.reward(&s, 0, xt, a), r as f64);
+    }
+
+    #[test]
+    fn test_configured_prices() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(5, 1.0, 1.0, 5, 1.0, 1.0, 2);
+        cprobs.price1 = 25.0;
+        cprobs.price2 = 40.0;
+        cprobs.move_cost = 7.5;
+        // Assert
+        let s = State { n1: 3, n2: 2 };
+        assert_eq!(cprobs.reward(&s, 2, 1, -2), 75.0);
+        assert_abs_diff_eq!(
+            cprobs.expected_reward(&s, 1),
+            25.0 * cprobs.e1[2] + 40.0 * cprobs.e2[3] - 7.5);
     }
 
     #[test_case(-2, 1, 0; "Negative reward")]
     #[test_case(8, -1, 1; "One car rented")]
     #[test_case(40, 0, 4; "No action")]
     #[test_case(20, 5, 3; "Several cars rented with action")]
-    fn test_cars_rented_calculation(r: i16, a: i16, xt: u8) {
-        assert_eq!(RentalAgency::cars_rented(r, a), xt);
+    fn test_cars_rented_calculation(r: i16, a: i8, xt: u32) {
+        let cprobs = RentalAgency::new(5, 1.0, 1.0, 5, 1.0, 1.0, 2);
+        let s = State { n1: 2, n2: 2 };
+        assert_eq!(cprobs.cars_rented(&s, r as f64, a), xt);
+    }
+
+    #[test_case(5, 5, 1, 0.0; "Free shuttle car")]
+    #[test_case(5, 5, 2, 2.0; "Shuttle car and one paid move")]
+    #[test_case(5, 5, -2, 4.0; "No free moves from 2 to 1")]
+    #[test_case(8, 2, 0, 4.0; "Parking fee at location 1")]
+    #[test_case(7, 8, -1, 6.0; "Move into parking fee")]
+    #[test_case(8, 8, 0, 8.0; "Parking fee at both locations")]
+    #[test_case(8, 7, 1, 4.0; "Shuttle car into parking fee")]
+    fn test_exercise_4_7_action_cost(n1: u8, n2: u8, a: i8, cost: f64) {
+        // Arrange
+        let mut cprobs = RentalAgency::new(10, 1.0, 1.0, 10, 1.0, 1.0, 2);
+        cprobs.variant = RewardVariant::Exercise4_7;
+        cprobs.parking_limit = 7;
+        let s = State { n1, n2 };
+        // Assert
+        assert_eq!(cprobs.action_cost(&s, a), cost);
+        assert_eq!(cprobs.reward(&s, 3, 0, a), 30.0 - cost);
+    }
+
+    #[test]
+    fn parking_limit_above_i8_range_never_charges_fee() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(10, 1.0, 1.0, 10, 1.0, 1.0, 2);
+        cprobs.variant = RewardVariant::Exercise4_7;
+        cprobs.parking_limit = 200;
+        // Assert
+        assert_eq!(cprobs.action_cost(&State { n1: 10, n2: 10 }, 0), 0.0);
     }
 
     #[test]
@@ -386,9 +830,10 @@ mod tests {
         let cprobs = RentalAgency::new(
             5, 2.0, 2.0,
             5, 2.0, 1.0, 2);
+        let pi = policy::Policy::build_from_agency(&cprobs);
         let s1 = State {n1: 0, n2: 0};
         // Act
-        let cv = cprobs.calc_value(&s1);
+        let cv = cprobs.calc_value(&s1, &pi);
         // Assert
         assert_eq!(cv, 0.0);
     }
@@ -399,9 +844,10 @@ mod tests {
         let cprobs = RentalAgency::new(
             5, 2.0, 1.0,
             5, 1.0, 2.0, 2);
+        let pi = policy::Policy::build_from_agency(&cprobs);
         let s1 = State {n1: 1, n2: 1};
         // Act
-        let cv = cprobs.calc_value(&s1);
+        let cv = cprobs.calc_value(&s1, &pi);
         // Assert
         assert!(cv > 0.0);
         assert!(cv < 20.0);
@@ -421,6 +867,133 @@ mod tests {
         }
     }
 
+    #[test]
+    fn next_probs_match_outcomes() {
+        // Arrange
+        let cprobs = RentalAgency::new(
+            5, 2.0, 1.0, 4, 1.0, 2.0, 2);
+        // Assert
+        for pt in cprobs.t1.sum_axis(ndarray::Axis(1)) {
+            assert_abs_diff_eq!(pt, 1.0, epsilon = 1e-12)
+        }
+        for pt in cprobs.t2.sum_axis(ndarray::Axis(1)) {
+            assert_abs_diff_eq!(pt, 1.0, epsilon = 1e-12)
+        }
+        // Probability of staying at 2 cars with no rentals at location #1,
+        // renting one car and getting one back, or renting two cars and
+        // getting two back.
+        let p = cprobs.x1[[2, 0]] * cprobs.y1[[2, 0]]
+            + cprobs.x1[[2, 1]] * cprobs.y1[[1, 1]]
+            + cprobs.x1[[2, 2]] * cprobs.y1[[0, 2]];
+        assert_abs_diff_eq!(cprobs.t1[[2, 2]], p, epsilon = 1e-12);
+        assert_abs_diff_eq!(cprobs.e2[[0]], 0.0);
+        assert_abs_diff_eq!(cprobs.e2[[1]], cprobs.x2[[1, 1]]);
+    }
+
+    #[test]
+    fn unmet_demand_is_poisson_tail() {
+        // Arrange
+        let cprobs = RentalAgency::new(
+            6, 3.0, 1.0, 4, 1.5, 2.0, 2);
+        // Assert
+        // Rentals plus customers turned away is the mean demand.
+        for (e, u) in cprobs.e1.iter().zip(cprobs.u1.iter()) {
+            assert_abs_diff_eq!(e + u, 3.0, epsilon = 1e-9);
+        }
+        for (e, u) in cprobs.e2.iter().zip(cprobs.u2.iter()) {
+            assert_abs_diff_eq!(e + u, 1.5, epsilon = 1e-9);
+        }
+        // Everyone is turned away from an empty lot.
+        assert_abs_diff_eq!(cprobs.u1[0], 3.0, epsilon = 1e-12);
+        let s = State { n1: 4, n2: 1 };
+        assert_abs_diff_eq!(cprobs.expected_unmet_demand(&s, 1),
+            cprobs.u1[3] + cprobs.u2[2]);
+    }
+
+    #[test]
+    fn lost_sale_penalty_reduces_reward() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(5, 3.0, 1.0, 5, 1.0, 2.0, 2);
+        let s = State { n1: 2, n2: 4 };
+        let before = cprobs.expected_reward(&s, -1);
+        // Act
+        cprobs.lost_sale_penalty = 5.0;
+        // Assert
+        assert_abs_diff_eq!(cprobs.expected_reward(&s, -1),
+            before - 5.0 * (cprobs.u1[3] + cprobs.u2[3]), epsilon = 1e-12);
+        // Not renting every car means no one was turned away.
+        assert_eq!(cprobs.reward(&s, 2, 1, -1), 28.0);
+        assert!(cprobs.reward(&s, 3, 1, -1) < 38.0);
+    }
+
+    #[test]
+    fn lost_sale_penalty_is_finite_when_tail_probability_rounds_to_zero() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(20, 1.0, 1.0, 20, 1.0, 1.0, 5);
+        cprobs.lost_sale_penalty = 2.0;
+        let s = State { n1: 20, n2: 20 };
+        let pi = policy::Policy::build_from_agency(&cprobs);
+        // Act
+        let r = cprobs.reward(&s, 20, 3, 0);
+        let (r_xt, _, _) = cprobs.calc_reward_prob(&s, &State { n1: 3, n2: 17 }, 0, 23);
+        // Assert
+        assert_eq!(cprobs.x1[[20, 20]], 0.0);
+        assert_eq!(r, 230.0);
+        assert!(r_xt.is_finite());
+        assert!(cprobs.calc_value_for_action(&s, 0, &pi).is_finite());
+    }
+
+    #[test]
+    fn transit_reward_leaves_moved_cars_off_both_lots() {
+        // Arrange
+        let agency = RentalAgency::new(10, 3.0, 3.0, 10, 4.0, 2.0, 3);
+        let s = State { n1: 5, n2: 3 };
+        // Act
+        let reward = agency.expected_transit_reward(&s, 2);
+        // Assert
+        assert_abs_diff_eq!(agency.expected_transit_reward(&s, 0),
+            agency.expected_reward(&s, 0));
+        assert_abs_diff_eq!(reward,
+            (agency.e1[3] + agency.e2[3]) * DEFAULT_RENTAL_PRICE - 2.0 * DEFAULT_MOVE_COST,
+            epsilon = 1e-9);
+        assert!(reward < agency.expected_reward(&s, 2));
+    }
+
+    #[test]
+    fn correlated_demand_keeps_marginals() {
+        // Arrange
+        let joint = JointDistribution::from_config("rent_joint",
+            &crate::config::JointConfig::BivariatePoisson {
+                mean1: 2.0, mean2: 3.0, common_mean: 1.5
+            }).unwrap();
+        let ret = |mean: f64| Distribution::poisson(mean);
+        // Act
+        let cprobs = RentalAgency::with_joint_rentals(
+            4, &joint, ret(1.0), 5, ret(2.0), 2);
+        // Assert
+        let t = cprobs.t_joint.as_ref().unwrap();
+        let width = 6;
+        for m in StateIterator::new(4, 5) {
+            let row = t.row(m.n1 as usize * width + m.n2 as usize);
+            assert_abs_diff_eq!(row.sum(), 1.0, epsilon = 1e-12);
+            // Each location on its own moves as if demand were independent.
+            for n1 in 0..=4 {
+                let p: f64 = row.slice(ndarray::s![n1 * width..(n1 + 1) * width]).sum();
+                assert_abs_diff_eq!(p, cprobs.t1[[m.n1 as usize, n1]], epsilon = 1e-9);
+            }
+            for x1 in 0..=m.n1 {
+                let p: f64 = (0..=m.n2)
+                    .map(|x2| cprobs.joint_rent_prob(m.n1, m.n2, x1, x2))
+                    .sum();
+                assert_abs_diff_eq!(p, cprobs.x1[[m.n1 as usize, x1 as usize]],
+                    epsilon = 1e-9);
+            }
+        }
+        // Both lots sell out together more often than if independent.
+        let both = cprobs.joint_rent_prob(2, 2, 2, 2);
+        assert!(both > cprobs.x1[[2, 2]] * cprobs.x2[[2, 2]]);
+    }
+
     #[test]
     fn view_array() {
         let cprobs = RentalAgency::new(
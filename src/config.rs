@@ -0,0 +1,804 @@
+//! Configuration
+//!
+//! Model parameters are read from a TOML file into a `CarConfig` struct.
+//! `RentalAgency::from_config` checks the parameters with
+//! `CarConfig::validate` before calculating any probabilities. Networks of
+//! more than two locations are read into a `NetworkConfig` struct, with one
+//! `[[locations]]` table per location. Demand that varies by day of the
+//! week is given as a `[[schedule]]` table for each phase of the period,
+//! one-way rentals are given with a `[one_way]` table, and moved cars can
+//! spend `transfer_days` days in transit.
+
+use std::{cmp, error, fmt};
+use std::path::PathBuf;
+use serde::Deserialize;
+use crate::cars::{
+    RewardVariant, DEFAULT_MOVE_COST, DEFAULT_PARKING_FEE, DEFAULT_PARKING_LIMIT,
+    DEFAULT_RENTAL_PRICE
+};
+use crate::dist::{Distribution, JointDistribution};
+use crate::oneway::RentalDuration;
+
+
+/// Hold information read from TOML configuration file.
+/// 
+/// Rentals and returns at each location are Poisson with the given mean
+/// (e.g., `rent_mean1`), unless a distribution table is given instead
+/// (e.g., `rent_dist1`). Correlated rental demand at both locations is
+/// given with a `rent_joint` table instead of the per-location rental
+/// fields. With a `one_way` table, every car returned was rented from one
+/// of the locations, so there are no return fields.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CarConfig {
+    pub max1: u8,
+    #[serde(default)]
+    pub rent_mean1: Option<f32>,
+    #[serde(default)]
+    pub return_mean1: Option<f32>,
+    #[serde(default)]
+    pub rent_dist1: Option<DistConfig>,
+    #[serde(default)]
+    pub return_dist1: Option<DistConfig>,
+    pub max2: u8,
+    #[serde(default)]
+    pub rent_mean2: Option<f32>,
+    #[serde(default)]
+    pub return_mean2: Option<f32>,
+    #[serde(default)]
+    pub rent_dist2: Option<DistConfig>,
+    #[serde(default)]
+    pub return_dist2: Option<DistConfig>,
+    /// Joint rental demand at both locations, replaces the rental means
+    /// and distributions
+    #[serde(default)]
+    pub rent_joint: Option<JointConfig>,
+    pub max_move: u8,
+    pub gamma: f64,
+    /// Revenue for each car rented, at either location
+    #[serde(default = "default_rental_price")]
+    pub rental_price: f64,
+    /// Revenue for each car rented at location #1, overrides `rental_price`
+    #[serde(default)]
+    pub rental_price1: Option<f64>,
+    /// Revenue for each car rented at location #2, overrides `rental_price`
+    #[serde(default)]
+    pub rental_price2: Option<f64>,
+    /// Cost for each car moved between locations
+    #[serde(default = "default_move_cost")]
+    pub move_cost: f64,
+    /// Rules for calculating the cost of an action, "standard" or "exercise4_7"
+    #[serde(default)]
+    pub reward_variant: RewardVariant,
+    /// Cars a location can keep overnight without a parking fee
+    #[serde(default = "default_parking_limit")]
+    pub parking_limit: u8,
+    /// Fee for keeping more than `parking_limit` cars at a location overnight
+    #[serde(default = "default_parking_fee")]
+    pub parking_fee: f64,
+    /// Goodwill cost for each customer turned away because the lot is empty
+    #[serde(default)]
+    pub lost_sale_penalty: f64,
+    /// Periodic demand schedule, e.g., one phase per day of the week. Each
+    /// phase overrides some of the rental and return distributions.
+    #[serde(default)]
+    pub schedule: Vec<PhaseConfig>,
+    /// Rented cars are returned at either location, replaces the return
+    /// means and distributions
+    #[serde(default)]
+    pub one_way: Option<OneWayConfig>,
+    /// Days moved cars spend in transit before they're available at the
+    /// receiving location. With 0, they're available the next morning.
+    #[serde(default)]
+    pub transfer_days: u8,
+}
+
+/// One-way rental parameters.
+/// 
+/// Each car rented at location #i is returned at location #j with
+/// probability `routing[i - 1][j - 1]`, at the end of the day
+/// `return_delay` days after it was rented. The number of days can be
+/// random instead, with a `duration` distribution table. Durations are
+/// only solved by the `one-way` command; other commands use independent
+/// returns.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OneWayConfig {
+    /// Number of cars on the lots and out on rent
+    pub fleet_size: u8,
+    /// Probability a car rented at each location (row) is returned at each
+    /// location (column)
+    pub routing: [[f64; 2]; 2],
+    /// Days a car is out on rent after the day it was rented
+    #[serde(default)]
+    pub return_delay: u8,
+    /// Distribution of days a car is out on rent, replaces `return_delay`
+    #[serde(default)]
+    pub duration: Option<DistConfig>,
+    /// Cars out on rent are counted by age in days, up to this many days.
+    /// Older cars share the last count. Defaults to 1 for geometric
+    /// durations, which are memoryless, and is required for other
+    /// durations, since each bucket multiplies the number of states.
+    #[serde(default)]
+    pub duration_buckets: Option<u8>,
+}
+
+/// Rental and return overrides for one phase of a periodic schedule.
+///
+/// Processes without an override use the base configuration's mean or
+/// distribution.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseConfig {
+    /// Label for output, e.g., "Fri"
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub rent_mean1: Option<f32>,
+    #[serde(default)]
+    pub return_mean1: Option<f32>,
+    #[serde(default)]
+    pub rent_dist1: Option<DistConfig>,
+    #[serde(default)]
+    pub return_dist1: Option<DistConfig>,
+    #[serde(default)]
+    pub rent_mean2: Option<f32>,
+    #[serde(default)]
+    pub return_mean2: Option<f32>,
+    #[serde(default)]
+    pub rent_dist2: Option<DistConfig>,
+    #[serde(default)]
+    pub return_dist2: Option<DistConfig>,
+}
+
+/// Distribution of the number of cars rented or returned in a day, selected
+/// with the `type` key.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DistConfig {
+    Poisson { mean: f64 },
+    /// Overdispersed demand with variance mean + mean^2 / dispersion
+    NegativeBinomial { mean: f64, dispersion: f64 },
+    Binomial { n: u64, p: f64 },
+    /// Zero with probability `zero_prob`, otherwise Poisson
+    ZeroInflatedPoisson { mean: f64, zero_prob: f64 },
+    /// Number of failures before the first success, with the given mean
+    Geometric { mean: f64 },
+    /// Histogram read from a CSV file with rows of cars,frequency
+    Empirical { path: PathBuf },
+}
+
+/// Joint distribution of the number of cars rented at both locations in a
+/// day, selected with the `type` key.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum JointConfig {
+    /// Poisson demand at each location with a shared component, so the
+    /// covariance is `common_mean`
+    BivariatePoisson { mean1: f64, mean2: f64, common_mean: f64 },
+    /// Histogram read from a CSV file with rows of cars1,cars2,frequency
+    Empirical { path: PathBuf },
+}
+
+fn default_rental_price() -> f64 {
+    DEFAULT_RENTAL_PRICE
+}
+
+fn default_move_cost() -> f64 {
+    DEFAULT_MOVE_COST
+}
+
+fn default_parking_limit() -> u8 {
+    DEFAULT_PARKING_LIMIT
+}
+
+fn default_parking_fee() -> f64 {
+    DEFAULT_PARKING_FEE
+}
+
+impl CarConfig {
+    /// Check that all parameters are within their allowed ranges,
+    /// including each distribution's parameters. Reads any empirical
+    /// histograms to check them.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..1.0).contains(&self.gamma) {
+            return Err(ConfigError::Gamma(self.gamma));
+        }
+        self.joint_rentals()?;
+        let processes = [
+            ("rent_mean1", self.rent_mean1, "rent_dist1", &self.rent_dist1),
+            ("return_mean1", self.return_mean1, "return_dist1", &self.return_dist1),
+            ("rent_mean2", self.rent_mean2, "rent_dist2", &self.rent_dist2),
+            ("return_mean2", self.return_mean2, "return_dist2", &self.return_dist2),
+        ];
+        for (mean_name, mean, dist_name, dist) in processes {
+            // The joint table replaces both rental processes, and one-way
+            // rentals replace both return processes.
+            let replaced_by = if mean_name.starts_with("rent") {
+                self.rent_joint.as_ref().map(|_| "rent_joint")
+            } else {
+                self.one_way.as_ref().map(|_| "one_way")
+            };
+            if let Some(name) = replaced_by {
+                if mean.is_some() || dist.is_some() {
+                    return Err(ConfigError::Distribution(name, format!(
+                        "give {name} or {mean_name} and {dist_name}, not both")));
+                }
+                continue;
+            }
+            check_process(mean_name, mean, dist_name, dist)?;
+        }
+        if let Some(one_way) = &self.one_way {
+            one_way.validate(self.max1, self.max2)?;
+            if !self.schedule.is_empty() {
+                return Err(ConfigError::OneWay(String::from(
+                    "one-way rentals don't support a schedule")));
+            }
+        }
+        if self.transfer_days > 0 && (self.one_way.is_some() || !self.schedule.is_empty()) {
+            return Err(ConfigError::TransferDays(String::from(
+                "transfers with lead time don't support one-way rentals or a schedule")));
+        }
+        for phase in &self.schedule {
+            let rent_override = phase.rent_mean1.is_some() || phase.rent_dist1.is_some()
+                || phase.rent_mean2.is_some() || phase.rent_dist2.is_some();
+            if self.rent_joint.is_some() && rent_override {
+                return Err(ConfigError::Distribution("rent_joint", String::from(
+                    "schedule phases can't override rentals with a joint table")));
+            }
+            let overrides = [
+                ("rent_mean1", phase.rent_mean1, "rent_dist1", &phase.rent_dist1),
+                ("return_mean1", phase.return_mean1, "return_dist1", &phase.return_dist1),
+                ("rent_mean2", phase.rent_mean2, "rent_dist2", &phase.rent_dist2),
+                ("return_mean2", phase.return_mean2, "return_dist2", &phase.return_dist2),
+            ];
+            for (mean_name, mean, dist_name, dist) in overrides {
+                if mean.is_some() || dist.is_some() {
+                    check_process(mean_name, mean, dist_name, dist)?;
+                }
+            }
+        }
+        let prices = [
+            ("rental_price", Some(self.rental_price)),
+            ("rental_price1", self.rental_price1),
+            ("rental_price2", self.rental_price2),
+            ("move_cost", Some(self.move_cost)),
+            ("parking_fee", Some(self.parking_fee)),
+            ("lost_sale_penalty", Some(self.lost_sale_penalty)),
+        ];
+        for (name, price) in prices {
+            if let Some(price) = price {
+                if !(price.is_finite() && price >= 0.0) {
+                    return Err(ConfigError::Price(name, price));
+                }
+            }
+        }
+        if self.max_move > cmp::min(self.max1, self.max2) / 2 {
+            return Err(ConfigError::MaxMove(self.max_move));
+        }
+        Ok(())
+    }
+
+    /// Rental and return distributions for location #1 and location #2.
+    /// 
+    /// Returns [rent1, return1, rent2, return2]. Reads any empirical
+    /// histograms, so call `validate` first. With a joint rental table, the
+    /// rental distributions are its marginals.
+    pub fn distributions(&self) -> Result<[Distribution; 4], ConfigError> {
+        let [rent1, rent2] = match self.joint_rentals()? {
+            Some(joint) => joint.marginals(),
+            None => [
+                distribution(self.rent_mean1, "rent_dist1", &self.rent_dist1)?,
+                distribution(self.rent_mean2, "rent_dist2", &self.rent_dist2)?,
+            ],
+        };
+        // One-way returns come from rentals, so none come from elsewhere.
+        let [return1, return2] = match self.one_way {
+            Some(_) => [Distribution::Empirical(vec![1.0]), Distribution::Empirical(vec![1.0])],
+            None => [
+                distribution(self.return_mean1, "return_dist1", &self.return_dist1)?,
+                distribution(self.return_mean2, "return_dist2", &self.return_dist2)?,
+            ],
+        };
+        Ok([rent1, return1, rent2, return2])
+    }
+
+    /// Joint rental demand at both locations, if it is correlated.
+    pub fn joint_rentals(&self) -> Result<Option<JointDistribution>, ConfigError> {
+        self.rent_joint.as_ref()
+            .map(|joint| JointDistribution::from_config("rent_joint", joint))
+            .transpose()
+    }
+
+    /// Rental and return distributions for each phase of the schedule.
+    ///
+    /// Returns one [rent1, return1, rent2, return2] array per phase, or
+    /// just the base distributions if there is no schedule. Call `validate`
+    /// first.
+    pub fn schedule_distributions(&self) -> Result<Vec<[Distribution; 4]>, ConfigError> {
+        let base = self.distributions()?;
+        if self.schedule.is_empty() {
+            return Ok(vec![base]);
+        }
+        self.schedule.iter()
+            .map(|phase| {
+                let [rent1, return1, rent2, return2] = &base;
+                Ok([
+                    phase_distribution(rent1, phase.rent_mean1, "rent_dist1", &phase.rent_dist1)?,
+                    phase_distribution(return1, phase.return_mean1, "return_dist1", &phase.return_dist1)?,
+                    phase_distribution(rent2, phase.rent_mean2, "rent_dist2", &phase.rent_dist2)?,
+                    phase_distribution(return2, phase.return_mean2, "return_dist2", &phase.return_dist2)?,
+                ])
+            })
+            .collect()
+    }
+}
+
+
+impl OneWayConfig {
+    /// Check that the fleet fits on the lots and each routing row is a
+    /// probability distribution.
+    pub fn validate(&self, max1: u8, max2: u8) -> Result<(), ConfigError> {
+        if self.fleet_size as u16 > max1 as u16 + max2 as u16 {
+            return Err(ConfigError::OneWay(format!(
+                "fleet_size must fit on both lots, got {}", self.fleet_size)));
+        }
+        for row in &self.routing {
+            let valid = row.iter().all(|p| (0.0..=1.0).contains(p))
+                && (row.iter().sum::<f64>() - 1.0).abs() < 1e-9;
+            if !valid {
+                return Err(ConfigError::OneWay(format!(
+                    "each routing row must be probabilities that sum to 1, got {row:?}")));
+            }
+        }
+        if self.duration.is_some() && self.return_delay != 0 {
+            return Err(ConfigError::OneWay(String::from(
+                "give return_delay or duration, not both")));
+        }
+        if let Some(duration) = &self.duration {
+            Distribution::from_config("duration", duration)?;
+        }
+        Ok(())
+    }
+
+    /// How long cars are out on rent. Reads any empirical histogram, so
+    /// call `validate` first.
+    pub fn rental_duration(&self) -> Result<RentalDuration, ConfigError> {
+        let Some(config) = &self.duration else {
+            return Ok(RentalDuration::fixed(self.return_delay));
+        };
+        let dist = Distribution::from_config("duration", config)?;
+        let buckets = match (self.duration_buckets, &dist) {
+            // No buckets would return every car the day it's rented.
+            (Some(0), _) => return Err(ConfigError::OneWay(String::from(
+                "duration_buckets must be at least 1 with a duration"))),
+            (Some(buckets), _) => buckets,
+            (None, Distribution::Geometric { .. }) => 1,
+            (None, _) => return Err(ConfigError::OneWay(String::from(
+                "duration_buckets is required for non-geometric durations"))),
+        };
+        Ok(RentalDuration::from_distribution(&dist, buckets))
+    }
+}
+
+
+/// Check that exactly one of a Poisson mean and a distribution is given,
+/// and that the mean is positive or the distribution's parameters are
+/// valid.
+fn check_process(
+    mean_name: &'static str, mean: Option<f32>,
+    dist_name: &'static str, dist: &Option<DistConfig>
+) -> Result<(), ConfigError> {
+    match (mean, dist) {
+        (Some(mean), None) if !(mean.is_finite() && mean > 0.0) =>
+            Err(ConfigError::Mean(mean_name, mean)),
+        (Some(_), None) => Ok(()),
+        (None, Some(dist)) => Distribution::from_config(dist_name, dist).map(|_| ()),
+        (Some(_), Some(_)) => Err(ConfigError::Distribution(
+            dist_name, format!("give {mean_name} or {dist_name}, not both"))),
+        (None, None) => Err(ConfigError::Distribution(
+            dist_name, format!("missing {mean_name} or {dist_name}"))),
+    }
+}
+
+
+/// A phase's override of a base distribution, if it has one.
+fn phase_distribution(
+    base: &Distribution, mean: Option<f32>, dist_name: &'static str,
+    dist: &Option<DistConfig>
+) -> Result<Distribution, ConfigError> {
+    match (mean, dist) {
+        (None, None) => Ok(base.clone()),
+        _ => distribution(mean, dist_name, dist),
+    }
+}
+
+
+/// Poisson distribution with the mean, or the configured distribution.
+fn distribution(
+    mean: Option<f32>, dist_name: &'static str, dist: &Option<DistConfig>
+) -> Result<Distribution, ConfigError> {
+    match dist {
+        Some(dist) => Distribution::from_config(dist_name, dist),
+        None => Ok(Distribution::poisson(f64::from(
+            mean.expect("Missing mean, call validate first.")))),
+    }
+}
+
+
+/// Hold information about a network of locations read from a TOML file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub locations: Vec<LocationConfig>,
+    /// Maximum number of cars moved between all locations each night
+    pub truck_capacity: u8,
+    pub gamma: f64,
+    /// Cost for each car moved between locations
+    #[serde(default = "default_move_cost")]
+    pub move_cost: f64,
+    /// Goodwill cost for each customer turned away because the lot is empty
+    #[serde(default)]
+    pub lost_sale_penalty: f64,
+}
+
+/// One location in a `NetworkConfig`.
+/// 
+/// Rentals and returns are Poisson with the given mean, unless a
+/// distribution table is given instead.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LocationConfig {
+    pub max: u8,
+    #[serde(default)]
+    pub rent_mean: Option<f32>,
+    #[serde(default)]
+    pub return_mean: Option<f32>,
+    #[serde(default)]
+    pub rent_dist: Option<DistConfig>,
+    #[serde(default)]
+    pub return_dist: Option<DistConfig>,
+    /// Revenue for each car rented at this location
+    #[serde(default = "default_rental_price")]
+    pub rental_price: f64,
+}
+
+impl NetworkConfig {
+    /// Check that all parameters are within their allowed ranges,
+    /// including each distribution's parameters. Reads any empirical
+    /// histograms to check them.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.locations.is_empty() {
+            return Err(ConfigError::NoLocations);
+        }
+        if !(0.0..1.0).contains(&self.gamma) {
+            return Err(ConfigError::Gamma(self.gamma));
+        }
+        for loc in &self.locations {
+            check_process("rent_mean", loc.rent_mean, "rent_dist", &loc.rent_dist)?;
+            check_process("return_mean", loc.return_mean, "return_dist", &loc.return_dist)?;
+        }
+        let prices = self.locations.iter()
+            .map(|loc| ("rental_price", loc.rental_price))
+            .chain([
+                ("move_cost", self.move_cost),
+                ("lost_sale_penalty", self.lost_sale_penalty),
+            ]);
+        for (name, price) in prices {
+            if !(price.is_finite() && price >= 0.0) {
+                return Err(ConfigError::Price(name, price));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LocationConfig {
+    /// Rental and return distributions. Call `NetworkConfig::validate` first.
+    pub fn distributions(&self) -> Result<[Distribution; 2], ConfigError> {
+        Ok([
+            distribution(self.rent_mean, "rent_dist", &self.rent_dist)?,
+            distribution(self.return_mean, "return_dist", &self.return_dist)?,
+        ])
+    }
+}
+
+
+/// Invalid configuration parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// Discount rate is not in [0, 1)
+    Gamma(f64),
+    /// Mean number of cars rented or returned is not positive
+    Mean(&'static str, f32),
+    /// Price or cost is negative
+    Price(&'static str, f64),
+    /// Max move is more than half of the smallest lot max
+    MaxMove(u8),
+    /// Network has no locations
+    NoLocations,
+    /// Agency uses options that networks don't support
+    Network(String),
+    /// Rental or return distribution is missing or invalid
+    Distribution(&'static str, String),
+    /// One-way rental parameters are invalid
+    OneWay(String),
+    /// Transfers with lead time can't be combined with other options
+    TransferDays(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Gamma(g) =>
+                write!(f, "gamma must be at least 0 and less than 1, got {g}"),
+            ConfigError::Mean(name, mean) =>
+                write!(f, "{name} must be greater than 0, got {mean}"),
+            ConfigError::Price(name, price) =>
+                write!(f, "{name} must not be negative, got {price}"),
+            ConfigError::MaxMove(max_move) => write!(f,
+                "max_move must be less than half of smallest lot max, got {max_move}"),
+            ConfigError::NoLocations =>
+                write!(f, "network must have at least one location"),
+            ConfigError::Network(msg) => write!(f, "network: {msg}"),
+            ConfigError::Distribution(name, msg) => write!(f, "{name}: {msg}"),
+            ConfigError::OneWay(msg) => write!(f, "one_way: {msg}"),
+            ConfigError::TransferDays(msg) => write!(f, "transfer_days: {msg}"),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cars::RentalAgency;
+    use test_case::test_case;
+
+    fn config(gamma: f64) -> CarConfig {
+        CarConfig {
+            max1: 3, rent_mean1: Some(1.0), return_mean1: Some(2.0),
+            rent_dist1: None, return_dist1: None,
+            max2: 3, rent_mean2: Some(2.0), return_mean2: Some(1.0),
+            rent_dist2: None, return_dist2: None,
+            rent_joint: None,
+            max_move: 1, gamma,
+            rental_price: DEFAULT_RENTAL_PRICE,
+            rental_price1: None, rental_price2: None,
+            move_cost: DEFAULT_MOVE_COST,
+            reward_variant: RewardVariant::Standard,
+            parking_limit: DEFAULT_PARKING_LIMIT,
+            parking_fee: DEFAULT_PARKING_FEE,
+            lost_sale_penalty: 0.0,
+            schedule: Vec::new(),
+            one_way: None,
+            transfer_days: 0,
+        }
+    }
+
+    #[test]
+    fn gamma_flows_into_agency() {
+        // Act
+        let agency = RentalAgency::from_config(&config(0.95)).unwrap();
+        // Assert
+        assert_eq!(agency.g, 0.95);
+        assert_eq!(crate::model::TransitionModel::build(&agency).g, 0.95);
+    }
+
+    #[test]
+    fn prices_flow_into_agency() {
+        // Arrange
+        let toml = "max1 = 3\nrent_mean1 = 1\nreturn_mean1 = 2\n\
+            max2 = 3\nrent_mean2 = 2\nreturn_mean2 = 1\nmax_move = 1\n\
+            gamma = 0.9\nrental_price = 30\nrental_price2 = 45.5\nmove_cost = 6\n\
+            lost_sale_penalty = 2.5\n";
+        let cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Assert
+        assert_eq!(agency.price1, 30.0);
+        assert_eq!(agency.price2, 45.5);
+        assert_eq!(agency.move_cost, 6.0);
+        assert_eq!(agency.lost_sale_penalty, 2.5);
+        assert_eq!(agency.variant, RewardVariant::Standard);
+    }
+
+    #[test]
+    fn exercise_4_7_variant_is_selectable() {
+        // Arrange
+        let toml = "max1 = 3\nrent_mean1 = 1\nreturn_mean1 = 2\n\
+            max2 = 3\nrent_mean2 = 2\nreturn_mean2 = 1\nmax_move = 1\n\
+            gamma = 0.9\nreward_variant = \"exercise4_7\"\nparking_limit = 2\n";
+        let cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Assert
+        assert_eq!(agency.variant, RewardVariant::Exercise4_7);
+        assert_eq!(agency.parking_limit, 2);
+        assert_eq!(agency.parking_fee, DEFAULT_PARKING_FEE);
+    }
+
+    #[test_case(1.0; "Gamma equal to one")]
+    #[test_case(1.5; "Gamma greater than one")]
+    #[test_case(-0.1; "Negative gamma")]
+    #[test_case(f64::NAN; "Gamma not a number")]
+    fn invalid_gamma_is_rejected(gamma: f64) {
+        // Act
+        let result = RentalAgency::from_config(&config(gamma));
+        // Assert
+        assert!(matches!(result, Err(ConfigError::Gamma(_))));
+    }
+
+    #[test]
+    fn invalid_mean_and_max_move_are_rejected() {
+        let mut cfg = config(0.9);
+        cfg.return_mean2 = Some(0.0);
+        assert_eq!(cfg.validate(), Err(ConfigError::Mean("return_mean2", 0.0)));
+        let mut cfg = config(0.9);
+        cfg.max_move = 2;
+        assert_eq!(cfg.validate(), Err(ConfigError::MaxMove(2)));
+        let mut cfg = config(0.9);
+        cfg.rental_price1 = Some(-5.0);
+        assert_eq!(cfg.validate(), Err(ConfigError::Price("rental_price1", -5.0)));
+    }
+
+    #[test]
+    fn network_config_is_read_and_validated() {
+        // Arrange
+        let toml = "truck_capacity = 3\ngamma = 0.9\n\
+            [[locations]]\nmax = 4\nrent_mean = 1\nreturn_mean = 2\n\
+            [[locations]]\nmax = 3\nrent_mean = 2\nreturn_mean = 1\nrental_price = 12\n";
+        // Act
+        let mut cfg: NetworkConfig = toml::from_str(toml).unwrap();
+        // Assert
+        assert_eq!(cfg.locations.len(), 2);
+        assert_eq!(cfg.locations[0].rental_price, DEFAULT_RENTAL_PRICE);
+        assert_eq!(cfg.locations[1].rental_price, 12.0);
+        assert_eq!(cfg.move_cost, DEFAULT_MOVE_COST);
+        assert_eq!(cfg.validate(), Ok(()));
+        cfg.locations[1].return_mean = Some(-1.0);
+        assert_eq!(cfg.validate(), Err(ConfigError::Mean("return_mean", -1.0)));
+        cfg.locations[1].return_mean = None;
+        cfg.locations[1].return_dist = Some(DistConfig::ZeroInflatedPoisson {
+            mean: 1.0, zero_prob: 2.0
+        });
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("return_dist", _))));
+        cfg.locations.clear();
+        assert_eq!(cfg.validate(), Err(ConfigError::NoLocations));
+    }
+
+    #[test]
+    fn distributions_are_selected_in_toml() {
+        // Arrange
+        let toml = "max1 = 3\nrent_mean1 = 1\nreturn_mean1 = 2\n\
+            max2 = 3\nreturn_mean2 = 1\nmax_move = 1\ngamma = 0.9\n\
+            [rent_dist2]\ntype = \"negative_binomial\"\nmean = 2\ndispersion = 0.5\n";
+        let cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Assert
+        assert!(matches!(agency.rent_dist1, Distribution::Poisson(_)));
+        assert!(matches!(agency.rent_dist2, Distribution::NegativeBinomial(_)));
+        assert_eq!(agency.rent_mean2, 2.0);
+    }
+
+    #[test]
+    fn joint_rentals_replace_rental_means() {
+        // Arrange
+        let toml = "max1 = 3\nreturn_mean1 = 2\nmax2 = 3\nreturn_mean2 = 1\n\
+            max_move = 1\ngamma = 0.9\n\
+            [rent_joint]\ntype = \"bivariate_poisson\"\nmean1 = 3\nmean2 = 2\n\
+            common_mean = 1\n";
+        let mut cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Assert
+        assert_eq!(agency.rent_mean1, 3.0);
+        assert_eq!(agency.rent_mean2, 2.0);
+        assert!(agency.rent_joint.is_some());
+        cfg.rent_mean2 = Some(2.0);
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("rent_joint", _))));
+    }
+
+    #[test]
+    fn one_way_replaces_return_means() {
+        // Arrange
+        let toml = "max1 = 4\nrent_mean1 = 1\nmax2 = 4\nrent_mean2 = 2\n\
+            max_move = 1\ngamma = 0.9\n\
+            [one_way]\nfleet_size = 6\nrouting = [[0.7, 0.3], [0.5, 0.5]]\n";
+        let mut cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let agency = RentalAgency::from_config(&cfg).unwrap();
+        // Assert
+        assert_eq!(cfg.one_way.as_ref().unwrap().return_delay, 0);
+        assert_eq!(agency.return_mean1, 0.0);
+        assert_eq!(agency.t1[[3, 3]], agency.x1[[3, 0]]);
+        cfg.one_way.as_mut().unwrap().routing[1] = [0.5, 0.6];
+        assert!(matches!(cfg.validate(), Err(ConfigError::OneWay(_))));
+        cfg.one_way.as_mut().unwrap().routing[1] = [0.5, 0.5];
+        cfg.one_way.as_mut().unwrap().fleet_size = 9;
+        assert!(matches!(cfg.validate(), Err(ConfigError::OneWay(_))));
+        cfg.one_way.as_mut().unwrap().fleet_size = 8;
+        cfg.return_mean1 = Some(1.0);
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("one_way", _))));
+        cfg.return_mean1 = None;
+        cfg.transfer_days = 1;
+        assert!(matches!(cfg.validate(), Err(ConfigError::TransferDays(_))));
+    }
+
+    #[test]
+    fn rental_duration_is_read_from_one_way_table() {
+        // Arrange
+        let toml = "fleet_size = 6\nrouting = [[1, 0], [0, 1]]\n\
+            [duration]\ntype = \"geometric\"\nmean = 3\n";
+        let mut cfg: OneWayConfig = toml::from_str(toml).unwrap();
+        // Act
+        let duration = cfg.rental_duration().unwrap();
+        // Assert
+        assert_eq!(duration.buckets(), 1);
+        assert_eq!(duration.hazard, vec![0.25, 0.25]);
+        cfg.duration = Some(DistConfig::Binomial { n: 3, p: 0.5 });
+        assert!(matches!(cfg.rental_duration(), Err(ConfigError::OneWay(_))));
+        cfg.duration_buckets = Some(0);
+        assert!(matches!(cfg.rental_duration(), Err(ConfigError::OneWay(_))));
+        cfg.duration_buckets = Some(2);
+        assert_eq!(cfg.rental_duration().unwrap().buckets(), 2);
+        cfg.return_delay = 1;
+        assert!(matches!(cfg.validate(4, 4), Err(ConfigError::OneWay(_))));
+        cfg.duration = None;
+        assert_eq!(cfg.rental_duration().unwrap(), RentalDuration::fixed(1));
+    }
+
+    #[test]
+    fn schedule_overrides_base_distributions() {
+        // Arrange
+        let toml = "max1 = 3\nrent_mean1 = 1\nreturn_mean1 = 2\n\
+            max2 = 3\nrent_mean2 = 2\nreturn_mean2 = 1\nmax_move = 1\ngamma = 0.9\n\
+            [[schedule]]\nname = \"Thu\"\n[[schedule]]\nname = \"Fri\"\nrent_mean2 = 4\n";
+        let mut cfg: CarConfig = toml::from_str(toml).unwrap();
+        // Act
+        let phases = cfg.schedule_distributions().unwrap();
+        // Assert
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases[0][2].mean(), 2.0);
+        assert_eq!(phases[1][2].mean(), 4.0);
+        assert_eq!(phases[1][0], phases[0][0]);
+        cfg.schedule[1].rent_dist2 = Some(DistConfig::Poisson { mean: 4.0 });
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("rent_dist2", _))));
+        cfg.schedule[1].rent_mean2 = Some(-4.0);
+        cfg.schedule[1].rent_dist2 = None;
+        assert_eq!(cfg.validate(), Err(ConfigError::Mean("rent_mean2", -4.0)));
+    }
+
+    #[test]
+    fn each_process_needs_one_distribution() {
+        let mut cfg = config(0.9);
+        cfg.rent_dist1 = Some(DistConfig::Poisson { mean: 1.0 });
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("rent_dist1", _))));
+        cfg.rent_mean1 = None;
+        assert_eq!(cfg.validate(), Ok(()));
+        cfg.rent_dist1 = None;
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("rent_dist1", _))));
+        let mut cfg = config(0.9);
+        cfg.return_mean2 = None;
+        cfg.return_dist2 = Some(DistConfig::Binomial { n: 4, p: 1.5 });
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("return_dist2", _))));
+        assert!(matches!(RentalAgency::from_config(&cfg),
+            Err(ConfigError::Distribution("return_dist2", _))));
+        cfg.return_dist2 = Some(DistConfig::Binomial { n: 4, p: 0.5 });
+        cfg.schedule = vec![PhaseConfig {
+            rent_dist1: Some(DistConfig::Geometric { mean: -1.0 }), ..Default::default()
+        }];
+        assert!(matches!(cfg.validate(),
+            Err(ConfigError::Distribution("rent_dist1", _))));
+    }
+}
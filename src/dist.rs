@@ -0,0 +1,470 @@
+//! Demand Distributions
+//!
+//! Sutton & Barto model the number of cars rented and returned each day
+//! as Poisson random variables. Real demand is often overdispersed, so each
+//! location's rental and return process can be any `Distribution`: Poisson,
+//! negative binomial, binomial, zero-inflated Poisson, geometric, or an
+//! empirical histogram read from a CSV file.
+//!
+//! Rental demand at the two locations can also be correlated, e.g., when
+//! both lots serve the same city and their busy days coincide. A
+//! `JointDistribution` gives the probability of each pair of demands.
+
+use std::path::Path;
+use ndarray::{s, Array2};
+use rand::Rng;
+use statrs::distribution::{
+    Binomial, Discrete, DiscreteCDF, NegativeBinomial, Poisson
+};
+use statrs::statistics::{DiscreteDistribution, Distribution as _};
+use crate::config::{ConfigError, DistConfig, JointConfig};
+
+
+/// Largest number of cars drawn by `Distribution::sample`.
+const MAX_SAMPLE: u64 = 1000;
+
+
+/// Distribution of the number of cars rented or returned in a day.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    Poisson(Poisson),
+    /// Number of failures before `r` successes. The variance is
+    /// mean + mean^2 / r, so smaller `r` means more overdispersion.
+    NegativeBinomial(NegativeBinomial),
+    Binomial(Binomial),
+    /// Zero with probability `zero_prob`, otherwise Poisson.
+    ZeroInflatedPoisson { zero_prob: f64, poisson: Poisson },
+    /// Number of failures before the first success, where each trial
+    /// succeeds with probability `p`.
+    Geometric { p: f64 },
+    /// Probability of each number of cars, starting from zero.
+    Empirical(Vec<f64>),
+}
+
+impl Distribution {
+    /// Poisson distribution with the given mean.
+    ///
+    /// Panics if the mean is not positive.
+    pub fn poisson(mean: f64) -> Distribution {
+        Distribution::Poisson(Poisson::new(mean).expect("Invalid Poisson mean."))
+    }
+
+    /// Create a distribution from configuration parameters.
+    ///
+    /// `name` is the configuration field, for error messages. Empirical
+    /// histograms are read from a CSV file with one row per number of cars
+    /// and two columns, the number of cars and its frequency. Frequencies
+    /// are normalized to probabilities.
+    pub fn from_config(
+        name: &'static str, config: &DistConfig
+    ) -> Result<Distribution, ConfigError> {
+        let invalid = |msg: String| ConfigError::Distribution(name, msg);
+        match config {
+            DistConfig::Poisson { mean } => Poisson::new(*mean)
+                .map(Distribution::Poisson)
+                .map_err(|err| invalid(err.to_string())),
+            DistConfig::NegativeBinomial { mean, dispersion } => {
+                if !(mean.is_finite() && *mean > 0.0) {
+                    return Err(invalid(format!("mean must be greater than 0, got {mean}")));
+                }
+                NegativeBinomial::new(*dispersion, dispersion / (dispersion + mean))
+                    .map(Distribution::NegativeBinomial)
+                    .map_err(|err| invalid(err.to_string()))
+            }
+            DistConfig::Binomial { n, p } => Binomial::new(*p, *n)
+                .map(Distribution::Binomial)
+                .map_err(|err| invalid(err.to_string())),
+            DistConfig::ZeroInflatedPoisson { mean, zero_prob } => {
+                if !(0.0..1.0).contains(zero_prob) {
+                    return Err(invalid(format!(
+                        "zero_prob must be at least 0 and less than 1, got {zero_prob}")));
+                }
+                let poisson = Poisson::new(*mean).map_err(|err| invalid(err.to_string()))?;
+                Ok(Distribution::ZeroInflatedPoisson { zero_prob: *zero_prob, poisson })
+            }
+            DistConfig::Geometric { mean } => {
+                if !(mean.is_finite() && *mean > 0.0) {
+                    return Err(invalid(format!("mean must be greater than 0, got {mean}")));
+                }
+                Ok(Distribution::Geometric { p: 1.0 / (1.0 + mean) })
+            }
+            DistConfig::Empirical { path } =>
+                Distribution::read_histogram(path).map_err(invalid),
+        }
+    }
+
+    /// Read an empirical histogram from a CSV file.
+    fn read_histogram(path: &Path) -> Result<Distribution, String> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .map_err(|err| format!("unable to read {}: {err}", path.display()))?;
+        let mut freqs: Vec<f64> = Vec::new();
+        for record in rdr.records() {
+            let record = record.map_err(|err| err.to_string())?;
+            let (Some(cars), Some(freq), 2) = (record.get(0), record.get(1), record.len())
+            else {
+                return Err(String::from("histogram rows must be cars,frequency"));
+            };
+            let cars: usize = cars.trim().parse().map_err(|_| format!("invalid cars {cars}"))?;
+            let freq: f64 = freq.trim().parse().map_err(|_| format!("invalid frequency {freq}"))?;
+            if !(freq.is_finite() && freq >= 0.0) {
+                return Err(format!("frequency must not be negative, got {freq}"));
+            }
+            if freqs.len() <= cars {
+                freqs.resize(cars + 1, 0.0);
+            }
+            freqs[cars] += freq;
+        }
+        let total: f64 = freqs.iter().sum();
+        if total <= 0.0 {
+            return Err(String::from("histogram has no observations"));
+        }
+        Ok(Distribution::Empirical(freqs.iter().map(|f| f / total).collect()))
+    }
+
+    /// Probability of exactly k cars.
+    pub fn pmf(&self, k: u64) -> f64 {
+        match self {
+            Distribution::Poisson(d) => d.pmf(k),
+            Distribution::NegativeBinomial(d) => d.pmf(k),
+            Distribution::Binomial(d) => d.pmf(k),
+            Distribution::ZeroInflatedPoisson { zero_prob, poisson } => {
+                let p = (1.0 - zero_prob) * poisson.pmf(k);
+                if k == 0 { zero_prob + p } else { p }
+            }
+            Distribution::Geometric { p } => p * (1.0 - p).powf(k as f64),
+            Distribution::Empirical(probs) =>
+                probs.get(k as usize).copied().unwrap_or(0.0),
+        }
+    }
+
+    /// Probability of k or fewer cars.
+    pub fn cdf(&self, k: u64) -> f64 {
+        match self {
+            Distribution::Poisson(d) => d.cdf(k),
+            Distribution::NegativeBinomial(d) => d.cdf(k),
+            Distribution::Binomial(d) => d.cdf(k),
+            Distribution::ZeroInflatedPoisson { zero_prob, poisson } =>
+                zero_prob + (1.0 - zero_prob) * poisson.cdf(k),
+            Distribution::Geometric { p } => 1.0 - (1.0 - p).powf(k as f64 + 1.0),
+            Distribution::Empirical(probs) =>
+                probs.iter().take(k as usize + 1).sum::<f64>().min(1.0),
+        }
+    }
+
+    /// Draw a random number of cars by inverting the cdf.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u64 {
+        let u: f64 = rng.gen();
+        let mut total = 0.0;
+        let mut k = 0;
+        // Rounding can leave the pmf's total just short of one.
+        while k < MAX_SAMPLE {
+            total += self.pmf(k);
+            if u < total {
+                break;
+            }
+            k += 1;
+        }
+        k
+    }
+
+    /// Expected number of cars.
+    pub fn mean(&self) -> f64 {
+        match self {
+            Distribution::Poisson(d) => d.mean().unwrap(),
+            Distribution::NegativeBinomial(d) => d.mean().unwrap(),
+            Distribution::Binomial(d) => d.mean().unwrap(),
+            Distribution::ZeroInflatedPoisson { zero_prob, poisson } =>
+                (1.0 - zero_prob) * poisson.mean().unwrap(),
+            Distribution::Geometric { p } => (1.0 - p) / p,
+            Distribution::Empirical(probs) => probs.iter().enumerate()
+                .map(|(k, p)| k as f64 * p)
+                .sum(),
+        }
+    }
+}
+
+
+/// Joint distribution of the number of cars customers want to rent at
+/// location #1 and location #2 in a day.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JointDistribution {
+    /// Bivariate Poisson. Demand at location k is `only_k + common`, where
+    /// the three components are independent Poisson, so the covariance is
+    /// the mean of `common`.
+    BivariatePoisson { common: Poisson, only1: Poisson, only2: Poisson },
+    /// Probability of each pair of demands. Indexes: cars at location #1,
+    /// cars at location #2.
+    Empirical(Array2<f64>),
+}
+
+impl JointDistribution {
+    /// Create a joint distribution from configuration parameters.
+    ///
+    /// `name` is the configuration field, for error messages. Empirical
+    /// histograms are read from a CSV file with rows of cars at location
+    /// #1, cars at location #2, and frequency.
+    pub fn from_config(
+        name: &'static str, config: &JointConfig
+    ) -> Result<JointDistribution, ConfigError> {
+        let invalid = |msg: String| ConfigError::Distribution(name, msg);
+        match config {
+            JointConfig::BivariatePoisson { mean1, mean2, common_mean } => {
+                if !(*common_mean > 0.0 && common_mean < mean1 && common_mean < mean2) {
+                    return Err(invalid(format!(
+                        "common_mean must be greater than 0 and less than both means, \
+                        got {common_mean}")));
+                }
+                let poisson = |mean: f64| Poisson::new(mean)
+                    .map_err(|err| invalid(err.to_string()));
+                Ok(JointDistribution::BivariatePoisson {
+                    common: poisson(*common_mean)?,
+                    only1: poisson(mean1 - common_mean)?,
+                    only2: poisson(mean2 - common_mean)?,
+                })
+            }
+            JointConfig::Empirical { path } =>
+                JointDistribution::read_histogram(path).map_err(invalid),
+        }
+    }
+
+    /// Read an empirical joint histogram from a CSV file.
+    fn read_histogram(path: &Path) -> Result<JointDistribution, String> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .map_err(|err| format!("unable to read {}: {err}", path.display()))?;
+        let mut rows: Vec<(usize, usize, f64)> = Vec::new();
+        for record in rdr.records() {
+            let record = record.map_err(|err| err.to_string())?;
+            let (Some(cars1), Some(cars2), Some(freq), 3) =
+                (record.get(0), record.get(1), record.get(2), record.len())
+            else {
+                return Err(String::from("histogram rows must be cars1,cars2,frequency"));
+            };
+            let cars1: usize = cars1.trim().parse().map_err(|_| format!("invalid cars {cars1}"))?;
+            let cars2: usize = cars2.trim().parse().map_err(|_| format!("invalid cars {cars2}"))?;
+            let freq: f64 = freq.trim().parse().map_err(|_| format!("invalid frequency {freq}"))?;
+            if !(freq.is_finite() && freq >= 0.0) {
+                return Err(format!("frequency must not be negative, got {freq}"));
+            }
+            rows.push((cars1, cars2, freq));
+        }
+        let total: f64 = rows.iter().map(|(_, _, freq)| freq).sum();
+        if total <= 0.0 {
+            return Err(String::from("histogram has no observations"));
+        }
+        let dim1 = rows.iter().map(|(cars1, _, _)| cars1 + 1).max().unwrap_or(1);
+        let dim2 = rows.iter().map(|(_, cars2, _)| cars2 + 1).max().unwrap_or(1);
+        let mut probs = Array2::<f64>::zeros((dim1, dim2));
+        for (cars1, cars2, freq) in rows {
+            probs[[cars1, cars2]] += freq / total;
+        }
+        Ok(JointDistribution::Empirical(probs))
+    }
+
+    /// Probability of exactly k1 cars at location #1 and k2 at location #2.
+    pub fn pmf(&self, k1: u64, k2: u64) -> f64 {
+        match self {
+            JointDistribution::BivariatePoisson { common, only1, only2 } =>
+                (0..=k1.min(k2))
+                    .map(|k| common.pmf(k) * only1.pmf(k1 - k) * only2.pmf(k2 - k))
+                    .sum(),
+            JointDistribution::Empirical(probs) =>
+                probs.get((k1 as usize, k2 as usize)).copied().unwrap_or(0.0),
+        }
+    }
+
+    /// Distributions of demand at location #1 and location #2 alone.
+    pub fn marginals(&self) -> [Distribution; 2] {
+        match self {
+            JointDistribution::BivariatePoisson { common, only1, only2 } => {
+                let common = common.mean().unwrap();
+                [
+                    Distribution::poisson(only1.mean().unwrap() + common),
+                    Distribution::poisson(only2.mean().unwrap() + common),
+                ]
+            }
+            JointDistribution::Empirical(probs) => [
+                Distribution::Empirical(probs.sum_axis(ndarray::Axis(1)).to_vec()),
+                Distribution::Empirical(probs.sum_axis(ndarray::Axis(0)).to_vec()),
+            ],
+        }
+    }
+
+    /// Draw random demands at location #1 and location #2.
+    ///
+    /// Bivariate Poisson demand is drawn as its three independent
+    /// components. Empirical demand inverts the cdf in index order.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> (u64, u64) {
+        match self {
+            JointDistribution::BivariatePoisson { common, only1, only2 } => {
+                let common = Distribution::Poisson(*common).sample(rng);
+                (
+                    common + Distribution::Poisson(*only1).sample(rng),
+                    common + Distribution::Poisson(*only2).sample(rng),
+                )
+            }
+            JointDistribution::Empirical(probs) => {
+                let u: f64 = rng.gen();
+                let mut total = 0.0;
+                for ((k1, k2), p) in probs.indexed_iter() {
+                    total += p;
+                    if u < total {
+                        return (k1 as u64, k2 as u64);
+                    }
+                }
+                // Rounding can leave the total just short of one.
+                let (dim1, dim2) = probs.dim();
+                (dim1 as u64 - 1, dim2 as u64 - 1)
+            }
+        }
+    }
+
+    /// Joint probabilities with the tails lumped into the last row and column.
+    ///
+    /// Indexes are [demand at location #1, demand at location #2], where
+    /// index `max1` means `max1` or more cars and index `max2` means `max2`
+    /// or more cars. Rentals at a location are the smaller of the demand and
+    /// the cars on the lot, so no lot needs more of the distribution.
+    pub fn truncated(&self, max1: u8, max2: u8) -> Array2<f64> {
+        let (dim1, dim2) = (max1 as usize + 1, max2 as usize + 1);
+        let mut probs = Array2::<f64>::zeros((dim1, dim2));
+        if let JointDistribution::Empirical(table) = self {
+            for ((k1, k2), p) in table.indexed_iter() {
+                probs[[k1.min(dim1 - 1), k2.min(dim2 - 1)]] += p;
+            }
+            return probs;
+        }
+        // The tails are whatever the marginals have left over.
+        let [marginal1, marginal2] = self.marginals();
+        for k1 in 0..dim1 - 1 {
+            for k2 in 0..dim2 - 1 {
+                probs[[k1, k2]] = self.pmf(k1 as u64, k2 as u64);
+            }
+        }
+        for k1 in 0..dim1 - 1 {
+            let inner: f64 = probs.slice(s![k1, ..dim2 - 1]).sum();
+            probs[[k1, dim2 - 1]] = (marginal1.pmf(k1 as u64) - inner).max(0.0);
+        }
+        for k2 in 0..dim2 - 1 {
+            let inner: f64 = probs.slice(s![..dim1 - 1, k2]).sum();
+            probs[[dim1 - 1, k2]] = (marginal2.pmf(k2 as u64) - inner).max(0.0);
+        }
+        let rest: f64 = probs.sum();
+        probs[[dim1 - 1, dim2 - 1]] = (1.0 - rest).max(0.0);
+        probs
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::{rngs::StdRng, SeedableRng};
+    use test_case::test_case;
+
+    #[test_case(DistConfig::Poisson { mean: 3.0 }; "Poisson")]
+    #[test_case(DistConfig::NegativeBinomial { mean: 3.0, dispersion: 1.5 };
+        "Negative binomial")]
+    #[test_case(DistConfig::Binomial { n: 10, p: 0.3 }; "Binomial")]
+    #[test_case(DistConfig::ZeroInflatedPoisson { mean: 5.0, zero_prob: 0.4 };
+        "Zero-inflated Poisson")]
+    #[test_case(DistConfig::Geometric { mean: 3.0 }; "Geometric")]
+    fn distributions_are_consistent(config: DistConfig) {
+        // Act
+        let dist = Distribution::from_config("rent_dist1", &config).unwrap();
+        // Assert
+        let probs: Vec<f64> = (0..200).map(|k| dist.pmf(k)).collect();
+        assert_abs_diff_eq!(probs.iter().sum::<f64>(), 1.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(dist.cdf(4), probs[..5].iter().sum::<f64>(), epsilon = 1e-9);
+        let mean: f64 = probs.iter().enumerate().map(|(k, p)| k as f64 * p).sum();
+        assert_abs_diff_eq!(dist.mean(), mean, epsilon = 1e-6);
+        assert_abs_diff_eq!(dist.mean(), 3.0, epsilon = 1e-9);
+        let mut rng = StdRng::seed_from_u64(7);
+        let total: u64 = (0..20_000).map(|_| dist.sample(&mut rng)).sum();
+        assert_abs_diff_eq!(total as f64 / 20_000.0, 3.0, epsilon = 0.1);
+    }
+
+    #[test]
+    fn negative_binomial_is_overdispersed() {
+        // Arrange
+        let config = DistConfig::NegativeBinomial { mean: 3.0, dispersion: 1.5 };
+        let dist = Distribution::from_config("rent_dist1", &config).unwrap();
+        // Act
+        let var: f64 = (0..200).map(|k| (k as f64 - 3.0).powi(2) * dist.pmf(k)).sum();
+        // Assert
+        assert_abs_diff_eq!(var, 3.0 + 9.0 / 1.5, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn empirical_histogram_is_normalized() {
+        // Arrange
+        let path = std::env::temp_dir().join("rustcar_histogram.csv");
+        std::fs::write(&path, "0,2\n1,5\n3,3\n").unwrap();
+        let config = DistConfig::Empirical { path: path.clone() };
+        // Act
+        let dist = Distribution::from_config("return_dist2", &config).unwrap();
+        // Assert
+        assert_eq!(dist, Distribution::Empirical(vec![0.2, 0.5, 0.0, 0.3]));
+        assert_abs_diff_eq!(dist.mean(), 1.4, epsilon = 1e-12);
+        assert_eq!(dist.cdf(10), 1.0);
+        std::fs::write(&path, "0,2\n1,-5\n").unwrap();
+        assert!(matches!(Distribution::from_config("return_dist2", &config),
+            Err(ConfigError::Distribution("return_dist2", _))));
+    }
+
+    #[test]
+    fn bivariate_poisson_shares_common_demand() {
+        // Arrange
+        let config = JointConfig::BivariatePoisson {
+            mean1: 3.0, mean2: 2.0, common_mean: 1.5
+        };
+        let joint = JointDistribution::from_config("rent_joint", &config).unwrap();
+        // Act
+        let [marginal1, marginal2] = joint.marginals();
+        let probs = joint.truncated(30, 30);
+        // Assert
+        assert_abs_diff_eq!(probs.sum(), 1.0, epsilon = 1e-12);
+        for k in 0..10 {
+            assert_abs_diff_eq!(probs.row(k).sum(), marginal1.pmf(k as u64), epsilon = 1e-9);
+            assert_abs_diff_eq!(probs.column(k).sum(), marginal2.pmf(k as u64), epsilon = 1e-9);
+        }
+        let cov: f64 = probs.indexed_iter()
+            .map(|((k1, k2), p)| (k1 as f64 - 3.0) * (k2 as f64 - 2.0) * p)
+            .sum();
+        assert_abs_diff_eq!(cov, 1.5, epsilon = 1e-6);
+        let mut rng = StdRng::seed_from_u64(5);
+        let draws: Vec<(u64, u64)> = (0..20_000).map(|_| joint.sample(&mut rng)).collect();
+        let sample_cov = draws.iter()
+            .map(|&(k1, k2)| (k1 as f64 - 3.0) * (k2 as f64 - 2.0))
+            .sum::<f64>() / 20_000.0;
+        assert_abs_diff_eq!(sample_cov, 1.5, epsilon = 0.15);
+        // Tails are lumped into the last row and column.
+        let small = joint.truncated(2, 1);
+        assert_abs_diff_eq!(small.sum(), 1.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(small.column(1).sum(), 1.0 - marginal2.pmf(0), epsilon = 1e-12);
+        assert!(JointDistribution::from_config("rent_joint", &JointConfig::BivariatePoisson {
+            mean1: 3.0, mean2: 1.0, common_mean: 1.0 }).is_err());
+    }
+
+    #[test]
+    fn empirical_joint_histogram_is_normalized() {
+        // Arrange
+        let path = std::env::temp_dir().join("rustcar_joint_histogram.csv");
+        std::fs::write(&path, "0,0,2\n1,2,5\n3,1,3\n").unwrap();
+        let config = JointConfig::Empirical { path };
+        // Act
+        let joint = JointDistribution::from_config("rent_joint", &config).unwrap();
+        // Assert
+        assert_eq!(joint.pmf(1, 2), 0.5);
+        assert_eq!(joint.pmf(4, 4), 0.0);
+        let [marginal1, marginal2] = joint.marginals();
+        assert_eq!(marginal1, Distribution::Empirical(vec![0.2, 0.5, 0.0, 0.3]));
+        assert_eq!(marginal2, Distribution::Empirical(vec![0.2, 0.3, 0.5]));
+        let probs = joint.truncated(1, 1);
+        assert_eq!(probs, ndarray::array![[0.2, 0.0], [0.0, 0.8]]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Finite Horizon
+//!
+//! The other solvers find a stationary policy for an agency that stays
+//! open forever. A seasonal agency closes after a fixed number of days, so
+//! the best action depends on how many days are left, e.g., it isn't worth
+//! paying to move cars that won't be rented before the lots close. The
+//! optimal policy for a season of T days has one action table per day,
+//! found with backward induction: the values after the last day are the
+//! terminal values, and each earlier day's values are one Bellman
+//! optimality backup of the next day's values.
+//!
+//! Days are numbered from 0, so day T - 1 is the last day of the season.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use rayon::prelude::*;
+use crate::model::TransitionModel;
+use crate::policy::Policy;
+
+
+/// Action tables and state values for each day of a season.
+pub struct SeasonPolicy {
+    /// One policy per day, in day order
+    pub days: Vec<Policy>,
+}
+
+impl SeasonPolicy {
+    /// Number of days in the season.
+    pub fn horizon(&self) -> usize {
+        self.days.len()
+    }
+
+    /// Action for a day and state.
+    pub fn get_action(&self, day: usize, n1: u8, n2: u8) -> i8 {
+        self.days[day].get_action(n1, n2)
+    }
+
+    /// Expected total reward from a state at the start of a day until the
+    /// end of the season, plus the discounted terminal value.
+    pub fn get_state_value(&self, day: usize, n1: u8, n2: u8) -> f64 {
+        self.days[day].get_state_value(n1, n2)
+    }
+
+    /// Write each day's action table to a CSV file in `dir`.
+    ///
+    /// Files are named day_001.csv, day_002.csv, etc., and can be read back
+    /// with `Policy::read_actions_csv`.
+    pub fn write_actions_csv(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        for (t, pi) in self.days.iter().enumerate() {
+            pi.write_actions_csv(&dir.join(format!("day_{:03}.csv", t + 1)))?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Find the optimal policy for each day of a season with backward
+/// induction.
+///
+/// `terminal` contains the value of each state after the last day, in
+/// `StateIterator` order. Each day's backups run in parallel. Ties keep
+/// the first valid action, as in `value_iteration`.
+pub fn backward_induction(
+    model: &TransitionModel, horizon: usize, terminal: &[f64]
+) -> SeasonPolicy {
+    let mut days = Vec::with_capacity(horizon);
+    let mut next_values = terminal.to_vec();
+    for _ in 0..horizon {
+        let mut pi = Policy::build_from_model(model);
+        let q: Vec<Vec<f64>> = model.actions.par_iter()
+            .map(|ams| ams.iter().map(|am| model.backup(am, &next_values)).collect())
+            .collect();
+        for (i, values) in q.into_iter().enumerate() {
+            let s = model.state(i);
+            let mut best = (0, f64::NEG_INFINITY);
+            for (am, value) in model.actions[i].iter().zip(values) {
+                pi.set_value(s.n1, s.n2, am.a, value);
+                if value > best.1 {
+                    best = (am.a, value);
+                }
+            }
+            pi.set_action(s.n1, s.n2, best.0);
+        }
+        next_values = pi.state_values().to_vec();
+        days.push(pi);
+    }
+    days.reverse();
+    SeasonPolicy { days }
+}
+
+
+/// Read terminal state values from a CSV file.
+///
+/// The file has one row for each number of cars at location #1 and one
+/// column for each number of cars at location #2, with no header row, as
+/// for `Policy::read_actions_csv`. Returns the values in `StateIterator`
+/// order.
+pub fn read_terminal_values(
+    path: &Path, max1: u8, max2: u8
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    let mut values = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        if record.len() != max2 as usize + 1 {
+            return Err("Terminal value table doesn't match lot sizes.".into());
+        }
+        for field in record.iter() {
+            values.push(field.trim().parse()?);
+        }
+    }
+    if values.len() != (max1 as usize + 1) * (max2 as usize + 1) {
+        return Err("Terminal value table doesn't match lot sizes.".into());
+    }
+    Ok(values)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cars::RentalAgency;
+    use crate::solver::StateIterator;
+    use approx::assert_abs_diff_eq;
+
+    fn model() -> TransitionModel {
+        TransitionModel::build(&RentalAgency::new(6, 3.0, 1.0, 6, 1.0, 3.0, 2))
+    }
+
+    #[test]
+    fn last_day_maximizes_expected_reward() {
+        // Arrange
+        let model = model();
+        // Act
+        let season = backward_induction(&model, 1, &vec![0.0; model.n_states()]);
+        // Assert
+        for (i, ams) in model.actions.iter().enumerate() {
+            let s = model.state(i);
+            let best = ams.iter().map(|am| am.reward).fold(f64::NEG_INFINITY, f64::max);
+            assert_abs_diff_eq!(season.get_state_value(0, s.n1, s.n2), best,
+                epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn long_season_starts_with_stationary_policy() {
+        // Arrange
+        let model = model();
+        let (pi, _) = crate::value_iteration(&model, 1e-9);
+        // Act
+        let season = backward_induction(&model, 300, &vec![0.0; model.n_states()]);
+        // Assert
+        assert_eq!(season.horizon(), 300);
+        assert_eq!(season.days[0].policy, pi.policy);
+        for s in StateIterator::new(6, 6) {
+            assert_abs_diff_eq!(season.get_state_value(0, s.n1, s.n2),
+                pi.get_state_value(s.n1, s.n2), epsilon = 1e-6);
+        }
+        // Moves on the last day only pay off with that day's rentals.
+        assert_ne!(season.days[299].policy, pi.policy);
+    }
+
+    #[test]
+    fn terminal_values_are_read_and_change_the_last_day() {
+        // Arrange
+        let model = model();
+        let path = std::env::temp_dir().join("rustcar_terminal_values.csv");
+        let rows: Vec<String> = (0..=6)
+            .map(|n1| vec![format!("{}", 20 * n1); 7].join(","))
+            .collect();
+        fs::write(&path, rows.join("\n")).unwrap();
+        // Act
+        let terminal = read_terminal_values(&path, 6, 6).unwrap();
+        let season = backward_induction(&model, 1, &terminal);
+        // Assert
+        assert_eq!(terminal[model.index(&crate::solver::State { n1: 2, n2: 5 })], 40.0);
+        assert_eq!(season.get_action(0, 0, 6), -2);
+        assert!(read_terminal_values(&path, 6, 5).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
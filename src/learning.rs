@@ -0,0 +1,414 @@
+//! Model-Free Control
+//!
+//! The dynamic programming solvers need the transition probabilities. A
+//! learning agent only sees the days it lives through, so it estimates the
+//! action values Q(s, a) from sampled days at a `DaySimulator` and stores
+//! them in `Policy::action_value`. When the estimates converge, the greedy
+//! policy is the same as the one from policy or value iteration on the same
+//! problem.
+//!
+//! Training runs in episodes of a fixed number of days, each starting from
+//! a random state so every state gets visited. Actions are ε-greedy: a
+//! random valid action with probability ε, otherwise the action with the
+//! highest estimate. ε shrinks by a constant factor after each episode.
+//!
+//! Each method updates an estimate toward the reward plus the discounted
+//! value of the next state, and they differ in what that value is.
+//! Q-learning uses the best action in the next state, whatever the agent
+//! does next, so it learns the optimal policy while exploring. SARSA uses
+//! the action the agent actually takes next, and Expected SARSA averages
+//! over the ε-greedy policy's actions, so both learn the value of the
+//! exploring policy and only find the optimal policy as ε shrinks.
+
+use std::error::Error;
+use std::path::Path;
+use rand::Rng;
+use rand::seq::IteratorRandom;
+use crate::cars::RentalAgency;
+use crate::model::TransitionModel;
+use crate::policy::Policy;
+use crate::sim::DaySimulator;
+use crate::solver::{State, StateIterator};
+
+
+/// Step size for each update of an action value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LearningRate {
+    /// Same step size for every update
+    Constant(f64),
+    /// 1 / n^power for the nth visit to a state and action. Estimates
+    /// converge for powers in (0.5, 1].
+    Polynomial(f64),
+}
+
+impl LearningRate {
+    /// Step size for the nth visit to a state and action, counting from 1.
+    pub fn alpha(&self, visits: u32) -> f64 {
+        match self {
+            LearningRate::Constant(alpha) => *alpha,
+            LearningRate::Polynomial(power) => (visits as f64).powf(-power),
+        }
+    }
+}
+
+
+/// Training settings for model-free control.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LearningParams {
+    /// Number of episodes
+    pub episodes: u32,
+    /// Days in each episode
+    pub steps: u32,
+    /// Step size schedule
+    pub alpha: LearningRate,
+    /// Exploration rate in the first episode
+    pub epsilon: f64,
+    /// Factor applied to the exploration rate after each episode
+    pub epsilon_decay: f64,
+    /// Smallest exploration rate
+    pub min_epsilon: f64,
+    /// Seed for the simulator and action choices
+    pub seed: u64,
+}
+
+impl Default for LearningParams {
+    fn default() -> LearningParams {
+        LearningParams {
+            episodes: 5_000,
+            steps: 100,
+            alpha: LearningRate::Polynomial(0.6),
+            epsilon: 1.0,
+            epsilon_decay: 0.999,
+            min_epsilon: 0.05,
+            seed: 0,
+        }
+    }
+}
+
+impl LearningParams {
+    /// Exploration rate for an episode, counting from 0.
+    pub fn epsilon(&self, episode: u32) -> f64 {
+        (self.epsilon * self.epsilon_decay.powf(episode as f64)).max(self.min_epsilon)
+    }
+}
+
+
+/// Temporal-difference target for updating an action value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TdMethod {
+    /// Off-policy: the best action value in the next state
+    QLearning,
+    /// On-policy: the value of the action the agent takes next
+    Sarsa,
+    /// On-policy: the expected action value in the next state under the
+    /// ε-greedy policy
+    ExpectedSarsa,
+}
+
+impl TdMethod {
+    /// Label for output, e.g., CSV column headers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TdMethod::QLearning => "q_learning",
+            TdMethod::Sarsa => "sarsa",
+            TdMethod::ExpectedSarsa => "expected_sarsa",
+        }
+    }
+}
+
+
+/// Result of model-free training.
+pub struct Training {
+    /// Policy that is greedy with respect to the learned action values
+    pub policy: Policy,
+    /// Total undiscounted reward earned in each episode, i.e., the
+    /// learning curve
+    pub episode_rewards: Vec<f64>,
+}
+
+
+/// Learn action values with tabular temporal-difference control.
+///
+/// Methods are compared with identical seeds. Q-learning and Expected
+/// SARSA see the same simulated days and make the same random choices
+/// until their action values differ. SARSA also chooses an action after
+/// each episode's last day for its target, so its later episodes draw
+/// different days. Action values of invalid actions stay at zero.
+pub fn td_control(
+    agency: &RentalAgency, params: &LearningParams, method: TdMethod
+) -> Training {
+    let mut pi = Policy::build_from_agency(agency);
+    let mut visits = ndarray::Array3::<u32>::zeros(pi.action_value.dim());
+    let mut sim = DaySimulator::new(agency, params.seed);
+    let mut episode_rewards = Vec::with_capacity(params.episodes as usize);
+    for episode in 0..params.episodes {
+        let epsilon = params.epsilon(episode);
+        let mut total = 0.0;
+        let mut s = sim.random_state();
+        // Only SARSA's target needs the next action before the update. The
+        // other methods choose it at the next step, with the updated
+        // estimates.
+        let mut next_a = None;
+        for _ in 0..params.steps {
+            let a = next_a.take()
+                .unwrap_or_else(|| epsilon_greedy(&pi, agency, &s, epsilon, &mut sim));
+            let (r, s2) = sim.step(&s, a);
+            total += r;
+            let next_value = match method {
+                TdMethod::QLearning =>
+                    pi.get_value(s2.n1, s2.n2, greedy_action(&pi, agency, &s2)),
+                TdMethod::Sarsa => {
+                    let a2 = epsilon_greedy(&pi, agency, &s2, epsilon, &mut sim);
+                    next_a = Some(a2);
+                    pi.get_value(s2.n1, s2.n2, a2)
+                }
+                TdMethod::ExpectedSarsa => expected_value(&pi, agency, &s2, epsilon),
+            };
+            let target = r + agency.g * next_value;
+            let a_idx = (a + pi.max_move as i8) as usize;
+            let n = &mut visits[[s.n1 as usize, s.n2 as usize, a_idx]];
+            *n += 1;
+            let q = pi.get_value(s.n1, s.n2, a);
+            pi.set_value(s.n1, s.n2, a, q + params.alpha.alpha(*n) * (target - q));
+            s = s2;
+        }
+        episode_rewards.push(total);
+    }
+    make_greedy(&mut pi, agency);
+    Training { policy: pi, episode_rewards }
+}
+
+
+/// Learn action values with tabular Q-learning.
+pub fn q_learning(agency: &RentalAgency, params: &LearningParams) -> Training {
+    td_control(agency, params, TdMethod::QLearning)
+}
+
+
+/// Expected action value of a state when actions are ε-greedy.
+fn expected_value(pi: &Policy, agency: &RentalAgency, s: &State, epsilon: f64) -> f64 {
+    let actions = agency.actions(s);
+    let explore = epsilon / actions.clone().count() as f64;
+    let greedy = greedy_action(pi, agency, s);
+    actions
+        .map(|a| {
+            let p = if a == greedy { 1.0 - epsilon + explore } else { explore };
+            p * pi.get_value(s.n1, s.n2, a)
+        })
+        .sum()
+}
+
+
+/// Write learning curves to a CSV file.
+///
+/// The file has an episode column, counting from 1, and one column of
+/// episode rewards for each named curve. Returns an error without writing
+/// the file if the curves aren't all the same length.
+pub fn write_learning_curves(
+    path: &Path, curves: &[(&str, &[f64])]
+) -> Result<(), Box<dyn Error>> {
+    let episodes = curves.first().map_or(0, |(_, rewards)| rewards.len());
+    if curves.iter().any(|(_, rewards)| rewards.len() != episodes) {
+        return Err("Learning curves have different numbers of episodes.".into());
+    }
+    let mut wtr = csv::Writer::from_path(path)?;
+    let header = std::iter::once("episode").chain(curves.iter().map(|(name, _)| *name));
+    wtr.write_record(header)?;
+    for k in 0..episodes {
+        let row = std::iter::once((k + 1).to_string())
+            .chain(curves.iter().map(|(_, rewards)| format!("{:.2}", rewards[k])));
+        wtr.write_record(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+
+/// Random valid action with probability `epsilon`, otherwise the greedy
+/// action.
+fn epsilon_greedy(
+    pi: &Policy, agency: &RentalAgency, s: &State, epsilon: f64,
+    sim: &mut DaySimulator
+) -> i8 {
+    let rng = sim.rng();
+    if rng.gen::<f64>() < epsilon {
+        agency.actions(s).choose(rng).expect("No valid actions.")
+    } else {
+        greedy_action(pi, agency, s)
+    }
+}
+
+
+/// Valid action with the highest action value. Ties keep the first valid
+/// action, as in `value_iteration`.
+pub(crate) fn greedy_action(pi: &Policy, agency: &RentalAgency, s: &State) -> i8 {
+    agency.actions(s)
+        .fold((0, f64::NEG_INFINITY), |best, a| {
+            let value = pi.get_value(s.n1, s.n2, a);
+            if value > best.1 { (a, value) } else { best }
+        })
+        .0
+}
+
+
+/// Set every state's action to its greedy action.
+pub(crate) fn make_greedy(pi: &mut Policy, agency: &RentalAgency) {
+    for s in StateIterator::new(agency.max1, agency.max2) {
+        let a = greedy_action(pi, agency, &s);
+        pi.set_action(s.n1, s.n2, a);
+    }
+}
+
+
+/// How far a learned policy is from the optimal policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolicyGap {
+    /// Number of states where the policies choose different actions
+    pub actions_differ: usize,
+    /// Largest shortfall of a state's value under the learned policy
+    pub max_value_gap: f64,
+    /// Average shortfall of a state's value under the learned policy
+    pub mean_value_gap: f64,
+}
+
+/// Compare a learned policy to the optimal policy.
+///
+/// The learned policy's own value estimates may be off, so its actions are
+/// evaluated with the transition model, stopping when no state value
+/// changes by more than `theta`. The optimal policy's state values should
+/// already be converged.
+pub fn policy_gap(
+    model: &TransitionModel, learned: &Policy, optimal: &Policy, theta: f64
+) -> PolicyGap {
+    let mut evaluated = Policy::build_from_model(model);
+    for i in 0..model.n_states() {
+        let s = model.state(i);
+        evaluated.set_action(s.n1, s.n2, learned.get_action(s.n1, s.n2));
+    }
+    crate::evaluate_policy(model, &mut evaluated, theta);
+    let mut gap = PolicyGap { actions_differ: 0, max_value_gap: 0.0, mean_value_gap: 0.0 };
+    for i in 0..model.n_states() {
+        let s = model.state(i);
+        if learned.get_action(s.n1, s.n2) != optimal.get_action(s.n1, s.n2) {
+            gap.actions_differ += 1;
+        }
+        let shortfall = optimal.get_state_value(s.n1, s.n2)
+            - evaluated.get_state_value(s.n1, s.n2);
+        gap.max_value_gap = gap.max_value_gap.max(shortfall);
+        gap.mean_value_gap += shortfall / model.n_states() as f64;
+    }
+    gap
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    fn agency() -> RentalAgency {
+        RentalAgency::new(4, 3.0, 1.0, 4, 1.0, 3.0, 2)
+    }
+
+    #[test]
+    fn schedules_decay() {
+        // Arrange
+        let params = LearningParams {
+            epsilon: 0.5, epsilon_decay: 0.5, min_epsilon: 0.1, ..Default::default()
+        };
+        // Assert
+        assert_eq!(params.epsilon(0), 0.5);
+        assert_eq!(params.epsilon(1), 0.25);
+        assert_eq!(params.epsilon(5), 0.1);
+        assert_eq!(LearningRate::Constant(0.2).alpha(10), 0.2);
+        assert_abs_diff_eq!(LearningRate::Polynomial(1.0).alpha(4), 0.25);
+    }
+
+    #[test]
+    fn q_learning_finds_a_near_optimal_policy() {
+        // Arrange
+        let agency = agency();
+        let model = TransitionModel::build(&agency);
+        let (optimal, _) = crate::value_iteration(&model, 1e-6);
+        let params = LearningParams { episodes: 2_000, ..Default::default() };
+        // Act
+        let learned = q_learning(&agency, &params).policy;
+        let gap = policy_gap(&model, &learned, &optimal, 1e-6);
+        // Assert
+        assert!(gap.max_value_gap >= -1e-4);
+        assert!(gap.mean_value_gap < 0.01 * optimal.get_state_value(2, 2),
+            "{gap:?}");
+        for s in StateIterator::new(4, 4) {
+            assert_abs_diff_eq!(learned.get_state_value(s.n1, s.n2),
+                optimal.get_state_value(s.n1, s.n2), epsilon = 3.0);
+        }
+    }
+
+    #[test_case(TdMethod::QLearning; "Q-learning")]
+    #[test_case(TdMethod::Sarsa; "SARSA")]
+    #[test_case(TdMethod::ExpectedSarsa; "Expected SARSA")]
+    fn same_seed_learns_the_same_policy(method: TdMethod) {
+        // Arrange
+        let agency = agency();
+        let params = LearningParams { episodes: 50, seed: 9, ..Default::default() };
+        // Act
+        let t1 = td_control(&agency, &params, method);
+        let t2 = td_control(&agency, &params, method);
+        // Assert
+        assert_eq!(t1.policy.action_value, t2.policy.action_value);
+        assert_eq!(t1.policy.policy, t2.policy.policy);
+        assert_eq!(t1.episode_rewards, t2.episode_rewards);
+        assert_eq!(t1.episode_rewards.len(), 50);
+    }
+
+    #[test_case(TdMethod::Sarsa; "SARSA")]
+    #[test_case(TdMethod::ExpectedSarsa; "Expected SARSA")]
+    fn on_policy_methods_learn_a_good_policy(method: TdMethod) {
+        // Arrange
+        let agency = agency();
+        let model = TransitionModel::build(&agency);
+        let (optimal, _) = crate::value_iteration(&model, 1e-6);
+        let params = LearningParams { episodes: 2_000, ..Default::default() };
+        // Act
+        let training = td_control(&agency, &params, method);
+        let gap = policy_gap(&model, &training.policy, &optimal, 1e-6);
+        // Assert
+        assert!(gap.mean_value_gap < 0.01 * optimal.get_state_value(2, 2),
+            "{gap:?}");
+        let early: f64 = training.episode_rewards[..100].iter().sum();
+        let late: f64 = training.episode_rewards[1_900..].iter().sum();
+        assert!(late > early);
+    }
+
+    #[test]
+    fn expected_value_weights_greedy_action() {
+        // Arrange
+        let agency = agency();
+        let mut pi = Policy::build_from_agency(&agency);
+        let s = State { n1: 1, n2: 0 };
+        pi.set_value(1, 0, 0, 10.0);
+        pi.set_value(1, 0, 1, 20.0);
+        // Act
+        let value = expected_value(&pi, &agency, &s, 0.5);
+        // Assert
+        assert_eq!(agency.actions(&s), 0..=1);
+        assert_abs_diff_eq!(value, 0.25 * 10.0 + 0.75 * 20.0);
+    }
+
+    #[test]
+    fn learning_curves_are_written_as_columns() {
+        // Arrange
+        let path = std::env::temp_dir().join("rustcar_learning_curves.csv");
+        let q = [1.0, 2.5];
+        let sarsa = [3.0, 4.0];
+        // Act
+        write_learning_curves(&path, &[("q_learning", &q), ("sarsa", &sarsa)]).unwrap();
+        // Assert
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "episode,q_learning,sarsa\n1,1.00,3.00\n2,2.50,4.00\n");
+        assert!(write_learning_curves(&path, &[("q_learning", &q), ("sarsa", &[3.0])])
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
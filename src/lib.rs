@@ -1,29 +1,266 @@
 #![allow(unused)]
-use std::cmp;
 
+use rayon::prelude::*;
+
+pub mod average;
+pub mod calendar;
 pub mod cars;
+pub mod config;
+pub mod dist;
+pub mod horizon;
+pub mod linear;
+pub mod learning;
+pub mod lp;
+pub mod model;
+pub mod montecarlo;
+pub mod network;
+pub mod oneway;
 pub mod policy;
+pub mod sim;
 pub mod solver;
+pub mod transit;
+
+
+/// Default stopping threshold for policy evaluation and value iteration.
+pub const THETA: f64 = 0.01;
+
+
+/// Amount of work a solver did before it converged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Convergence {
+    /// Number of sweeps over the state space
+    pub sweeps: u32,
+    /// Number of policy improvement steps
+    pub improvements: u32,
+}
+
+
+/// Find the optimal policy with policy iteration.
+///
+/// Alternate between evaluating the current policy and greedily improving
+/// it until the policy no longer changes. Policy evaluation repeats sweeps
+/// over all states until the largest change in any state's value is less
+/// than `theta`. The returned policy contains the final action table and
+/// the value estimates for the final policy.
+pub fn learn(agency: &cars::RentalAgency, theta: f64) -> policy::Policy {
+    policy_iteration(&model::TransitionModel::build(agency), theta).0
+}
+
+
+/// Policy iteration that also reports the work needed to converge.
+pub fn policy_iteration(
+    model: &model::TransitionModel, theta: f64
+) -> (policy::Policy, Convergence) {
+    let mut pi = policy::Policy::build_from_model(model);
+    let mut conv = Convergence::default();
+    loop {
+        conv.sweeps += evaluate_policy(model, &mut pi, theta);
+        conv.improvements += 1;
+        if improve_policy(model, &mut pi) {
+            break;
+        }
+    }
+    (pi, conv)
+}
+
+
+/// Find the optimal policy with value iteration.
+///
+/// Each sweep replaces every state's value with the value of its best
+/// valid action (a Bellman optimality backup). Sweeps stop when the largest
+/// change in any state's value is less than `theta`, then the policy is
+/// made greedy with respect to the final values.
+pub fn value_iteration(
+    model: &model::TransitionModel, theta: f64
+) -> (policy::Policy, Convergence) {
+    let mut pi = policy::Policy::build_from_model(model);
+    let mut conv = Convergence::default();
+    loop {
+        let mut delta: f64 = 0.0;
+        for (i, values) in action_values(model, &pi).into_iter().enumerate() {
+            let s = model.state(i);
+            let old_value = pi.get_state_value(s.n1, s.n2);
+            let mut best = (0, f64::NEG_INFINITY);
+            for (am, value) in model.actions[i].iter().zip(values) {
+                pi.set_value(s.n1, s.n2, am.a, value);
+                if value > best.1 {
+                    best = (am.a, value);
+                }
+            }
+            pi.set_action(s.n1, s.n2, best.0);
+            delta = delta.max((best.1 - old_value).abs());
+        }
+        conv.sweeps += 1;
+        if delta < theta {
+            break;
+        }
+    }
+    improve_policy(model, &mut pi);
+    conv.improvements += 1;
+    (pi, conv)
+}
+
+
+/// Value iteration on any table of action models.
+///
+/// `actions` lists the models for each valid action in each state, and the
+/// states need not be (n1, n2) pairs. Sweeps are synchronous and run in
+/// parallel, as in `value_iteration`, and stop when the largest change in
+/// any state's value is less than `theta`. Returns the position of the best
+/// action in each state's list, the state values, and the work done. Ties
+/// keep the first action in the list.
+pub fn table_value_iteration<A: Sync>(
+    actions: &[Vec<model::ActionModel<A>>], g: f64, theta: f64
+) -> (Vec<usize>, Vec<f64>, Convergence) {
+    let mut best_actions = vec![0; actions.len()];
+    let mut values = vec![0.0; actions.len()];
+    let mut conv = Convergence::default();
+    loop {
+        let best: Vec<(usize, f64)> = actions.par_iter()
+            .map(|ams| {
+                ams.iter().enumerate()
+                    .map(|(k, am)| (k, am.reward + g * am.expected_value(&values)))
+                    .fold((0, f64::NEG_INFINITY),
+                        |best, (k, value)| if value > best.1 { (k, value) } else { best })
+            })
+            .collect();
+        let mut delta: f64 = 0.0;
+        for (i, (k, value)) in best.into_iter().enumerate() {
+            delta = delta.max((value - values[i]).abs());
+            best_actions[i] = k;
+            values[i] = value;
+        }
+        conv.sweeps += 1;
+        if delta < theta {
+            break;
+        }
+    }
+    conv.improvements += 1;
+    (best_actions, values, conv)
+}
+
+
+/// Back up the value of every valid action in every state.
+///
+/// States are backed up in parallel on the current rayon thread pool. All
+/// backups use the policy's state values from before the sweep, so the
+/// results don't depend on the number of threads. Indexes of the returned
+/// vector are state index, action order.
+pub fn action_values(
+    model: &model::TransitionModel, pi: &policy::Policy
+) -> Vec<Vec<f64>> {
+    let v = pi.state_values();
+    model.actions.par_iter()
+        .map(|ams| ams.iter().map(|am| model.backup(am, v)).collect())
+        .collect()
+}
+
+
+/// Find the optimal policy with modified policy iteration.
+///
+/// Each iteration greedily improves the policy, which also backs up every
+/// state's value once, and then runs up to `k - 1` more policy evaluation
+/// sweeps. With `k = 1` this is value iteration. With `k = None` the policy
+/// is fully evaluated after each improvement, as in policy iteration.
+/// Stops when an improvement leaves the policy unchanged and changes no
+/// state's value by more than `theta`.
+pub fn modified_policy_iteration(
+    model: &model::TransitionModel, k: Option<u32>, theta: f64
+) -> (policy::Policy, Convergence) {
+    let mut pi = policy::Policy::build_from_model(model);
+    let mut conv = Convergence::default();
+    loop {
+        let old_values = pi.value.clone();
+        let stable = improve_policy(model, &mut pi);
+        conv.sweeps += 1;
+        conv.improvements += 1;
+        let delta = (&pi.value - &old_values)
+            .fold(0.0, |d: f64, v| d.max(v.abs()));
+        if stable && delta < theta {
+            break;
+        }
+        let max_sweeps = k.map(|k| k.saturating_sub(1));
+        conv.sweeps += evaluate_policy_partially(
+            model, &mut pi, theta, max_sweeps);
+    }
+    (pi, conv)
+}
+
+
+/// Estimate the value of each state, assuming actions are per the policy.
+///
+/// Updates the policy's value estimates in place, sweeping over all states
+/// until the largest change in a sweep is less than `theta`. Returns the
+/// number of sweeps.
+pub fn evaluate_policy(
+    model: &model::TransitionModel, pi: &mut policy::Policy, theta: f64
+) -> u32 {
+    evaluate_policy_partially(model, pi, theta, None)
+}
 
 
-pub fn learn(mut agency: cars::RentalAgency) {
-    let mut pi = policy::Policy::build_from_agency(&agency);
-    
-    // Estimate values for all states and actions.
-    for s1 in solver::StateIterator::new(agency.max1, agency.max2) {
-        let min_move = -1 * cmp::min(
-            cmp::min(agency.max_move, s1.n2),
-            agency.max1 - s1.n1
-        ) as i8;
-        let max_move = cmp::min(
-            cmp::min(agency.max_move, s1.n1),
-            agency.max2 - s1.n2) as i8;
-        for a in min_move..(max_move + 1) {
-            let val = agency.calc_value_for_action(&s1, a, &pi);
-            pi.set_value(s1.n1, s1.n2, a, val);
-            println!("State: {s1}, Action: {a}, Value: {val}")
+/// Policy evaluation that stops after at most `max_sweeps` sweeps.
+///
+/// Returns the number of sweeps.
+pub fn evaluate_policy_partially(
+    model: &model::TransitionModel, pi: &mut policy::Policy, theta: f64,
+    max_sweeps: Option<u32>
+) -> u32 {
+    let mut sweeps = 0;
+    while max_sweeps.is_none_or(|max| sweeps < max) {
+        let v = pi.state_values();
+        let values: Vec<f64> = (0..model.n_states()).into_par_iter()
+            .map(|i| {
+                let s = model.state(i);
+                let a = pi.get_action(s.n1, s.n2);
+                let am = model.action(i, a).expect("Invalid action in policy.");
+                model.backup(am, v)
+            })
+            .collect();
+        let mut delta: f64 = 0.0;
+        for (i, value) in values.into_iter().enumerate() {
+            let s = model.state(i);
+            let old_value = pi.get_state_value(s.n1, s.n2);
+            pi.set_value(s.n1, s.n2, pi.get_action(s.n1, s.n2), value);
+            delta = delta.max((value - old_value).abs());
+        }
+        sweeps += 1;
+        if delta < theta {
+            break;
+        }
+    }
+    sweeps
+}
+
+
+/// Make the policy greedy with respect to the current value estimates.
+///
+/// Calculates the value of every valid action for each state and selects
+/// the action with the highest value. The current action is kept unless
+/// another action is strictly better, so ties can't cause the policy to
+/// oscillate. Returns true if the policy did not change.
+pub fn improve_policy(
+    model: &model::TransitionModel, pi: &mut policy::Policy
+) -> bool {
+    let mut stable = true;
+    for (i, values) in action_values(model, pi).into_iter().enumerate() {
+        let s = model.state(i);
+        let old_action = pi.get_action(s.n1, s.n2);
+        let mut best_action = old_action;
+        let mut best_value = f64::NEG_INFINITY;
+        for (am, value) in model.actions[i].iter().zip(values) {
+            pi.set_value(s.n1, s.n2, am.a, value);
+            if value > best_value || (value == best_value && am.a == old_action) {
+                best_action = am.a;
+                best_value = value;
+            }
+        }
+        if best_action != old_action {
+            pi.set_action(s.n1, s.n2, best_action);
+            stable = false;
         }
     }
+    stable
 }
 
 
@@ -36,6 +273,103 @@ mod tests {
         // Arrange
         let cprobs = cars::RentalAgency::new(
             3, 1.0, 1.0, 3, 1.0, 1.0, 1);
-        learn(cprobs);
+        // Act
+        let pi = learn(&cprobs, THETA);
+        // Assert
+        for s in solver::StateIterator::new(3, 3) {
+            let a = pi.get_action(s.n1, s.n2);
+            assert!(cprobs.actions(&s).contains(&a));
+        }
+    }
+
+    #[test]
+    fn learn_policy_is_stable() {
+        // Arrange
+        let cprobs = cars::RentalAgency::new(
+            3, 2.0, 1.0, 3, 1.0, 2.0, 1);
+        // Act
+        let mut pi = learn(&cprobs, THETA);
+        // Assert
+        let model = model::TransitionModel::build(&cprobs);
+        assert!(improve_policy(&model, &mut pi));
+    }
+
+    #[test]
+    fn evaluated_values_satisfy_bellman_equation() {
+        // Arrange
+        let cprobs = cars::RentalAgency::new(
+            3, 2.0, 1.0, 3, 1.0, 2.0, 1);
+        let model = model::TransitionModel::build(&cprobs);
+        let mut pi = policy::Policy::build_from_model(&model);
+        // Act
+        evaluate_policy(&model, &mut pi, 1e-6);
+        // Assert
+        for s in solver::StateIterator::new(3, 3) {
+            let v = pi.get_state_value(s.n1, s.n2);
+            assert!((v - cprobs.calc_value(&s, &pi)).abs() < 1e-4);
+            assert!(v >= 0.0);
+        }
+        assert!(pi.get_state_value(3, 3) > pi.get_state_value(0, 0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn value_iteration_matches_policy_iteration() {
+        // Arrange
+        let cprobs = cars::RentalAgency::new(
+            5, 1.0, 2.0, 5, 2.0, 1.0, 2);
+        let model = model::TransitionModel::build(&cprobs);
+        // Act
+        let (pi_pi, _) = policy_iteration(&model, 1e-4);
+        let (pi_vi, conv) = value_iteration(&model, 1e-4);
+        // Assert
+        assert!(conv.sweeps > 1);
+        assert_eq!(conv.improvements, 1);
+        assert_eq!(pi_vi.policy, pi_pi.policy);
+        for s in solver::StateIterator::new(5, 5) {
+            let v_pi = pi_pi.get_state_value(s.n1, s.n2);
+            let v_vi = pi_vi.get_state_value(s.n1, s.n2);
+            assert!((v_pi - v_vi).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn modified_policy_iteration_matches_extremes() {
+        // Arrange
+        let cprobs = cars::RentalAgency::new(
+            5, 1.0, 2.0, 5, 2.0, 1.0, 2);
+        let model = model::TransitionModel::build(&cprobs);
+        let (pi_vi, _) = value_iteration(&model, 1e-4);
+        // Act
+        let (pi_1, conv_1) = modified_policy_iteration(&model, Some(1), 1e-4);
+        let (pi_3, conv_3) = modified_policy_iteration(&model, Some(3), 1e-4);
+        let (pi_inf, conv_inf) = modified_policy_iteration(&model, None, 1e-4);
+        // Assert
+        assert_eq!(conv_1.sweeps, conv_1.improvements);
+        assert!(conv_3.improvements < conv_1.improvements);
+        assert!(conv_inf.improvements <= conv_3.improvements);
+        for pi in [&pi_1, &pi_3, &pi_inf] {
+            assert_eq!(pi.policy, pi_vi.policy);
+        }
+    }
+
+    #[test]
+    fn results_dont_depend_on_thread_count() {
+        // Arrange
+        let cprobs = cars::RentalAgency::new(
+            6, 3.0, 2.0, 5, 2.0, 3.0, 2);
+        let model = model::TransitionModel::build(&cprobs);
+        let solve = |threads| {
+            rayon::ThreadPoolBuilder::new().num_threads(threads).build()
+                .unwrap()
+                .install(|| value_iteration(&model, 1e-6))
+        };
+        // Act
+        let (pi_1, conv_1) = solve(1);
+        let (pi_4, conv_4) = solve(4);
+        // Assert
+        assert_eq!(conv_1, conv_4);
+        assert_eq!(pi_1.policy, pi_4.policy);
+        assert_eq!(pi_1.value, pi_4.value);
+        assert_eq!(pi_1.action_value, pi_4.action_value);
+    }
+}
@@ -0,0 +1,300 @@
+//! Exact Policy Evaluation
+//!
+//! For a fixed policy, the state values satisfy the linear system
+//! (I - gP)v = r, where P is the state transition matrix and r is the
+//! expected reward for each state when following the policy. This module
+//! assembles P and r from the `TransitionModel` and solves the system
+//! directly instead of iterating policy evaluation sweeps.
+
+use std::{error, fmt};
+use ndarray::{Array1, Array2};
+use crate::model::TransitionModel;
+use crate::policy::Policy;
+
+
+/// Method for solving the linear system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinearSolver {
+    /// Gaussian elimination with partial pivoting.
+    Dense,
+    /// Biconjugate gradient stabilized method on a sparse matrix. Stops
+    /// when the residual norm is less than `tol` times the norm of r.
+    BiCgStab { tol: f64, max_iter: usize },
+}
+
+
+/// An iterative solver stopped before the residual was small enough.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NotConverged {
+    /// Number of iterations run
+    pub iterations: usize,
+    /// Residual norm divided by the norm of b
+    pub relative_residual: f64,
+}
+
+impl fmt::Display for NotConverged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "linear solver didn't converge in {} iterations, relative residual {:.3e}",
+            self.iterations, self.relative_residual)
+    }
+}
+
+impl error::Error for NotConverged {}
+
+
+/// Transition matrix and expected rewards for a fixed policy.
+pub struct PolicyModel {
+    /// P[s, s2] is the probability of moving from state s to state s2
+    pub p: Array2<f64>,
+    /// Expected reward for each state
+    pub r: Array1<f64>,
+    /// Discount rate
+    pub g: f64,
+}
+
+impl PolicyModel {
+    /// Assemble the transition matrix and reward vector for a policy.
+    pub fn build(model: &TransitionModel, pi: &Policy) -> PolicyModel {
+        let n_states = model.n_states();
+        let mut p = Array2::<f64>::zeros((n_states, n_states));
+        let mut r = Array1::<f64>::zeros(n_states);
+        for i in 0..n_states {
+            let s = model.state(i);
+            let am = model.action(i, pi.get_action(s.n1, s.n2))
+                .expect("Invalid action in policy.");
+            for (j, prob) in am.next() {
+                p[[i, j]] = prob;
+            }
+            r[i] = am.reward;
+        }
+        PolicyModel { p, r, g: model.g }
+    }
+
+    /// The system matrix, I - gP.
+    pub fn system_matrix(&self) -> Array2<f64> {
+        Array2::<f64>::eye(self.r.len()) - self.g * &self.p
+    }
+
+    /// Solve (I - gP)v = r for the state values.
+    pub fn solve(&self, solver: LinearSolver) -> Result<Array1<f64>, NotConverged> {
+        let a = self.system_matrix();
+        match solver {
+            LinearSolver::Dense => Ok(solve_dense(&a, &self.r)),
+            LinearSolver::BiCgStab { tol, max_iter } =>
+                bicgstab(&SparseMatrix::from_dense(&a), &self.r, tol, max_iter),
+        }
+    }
+}
+
+
+/// Calculate the exact value of each state when following the policy.
+///
+/// Writes the values into the policy's state and action value tables.
+/// Returns an error and leaves the policy's values unchanged if an
+/// iterative solver doesn't converge.
+pub fn evaluate_policy_exactly(
+    model: &TransitionModel, pi: &mut Policy, solver: LinearSolver
+) -> Result<(), NotConverged> {
+    let v = PolicyModel::build(model, pi).solve(solver)?;
+    for (i, &v_s) in v.iter().enumerate() {
+        let s = model.state(i);
+        let a = pi.get_action(s.n1, s.n2);
+        pi.set_value(s.n1, s.n2, a, v_s);
+    }
+    Ok(())
+}
+
+
+/// Solve Ax = b with Gaussian elimination and partial pivoting.
+///
+/// Panics if A is singular.
+pub fn solve_dense(a: &Array2<f64>, b: &Array1<f64>) -> Array1<f64> {
+    let n = b.len();
+    let mut m = a.clone();
+    let mut x = b.clone();
+    for col in 0..n {
+        // Swap the row with the largest pivot into place.
+        let pivot = (col..n)
+            .max_by(|&i, &j| m[[i, col]].abs().total_cmp(&m[[j, col]].abs()))
+            .expect("Empty matrix");
+        if m[[pivot, col]].abs() < f64::EPSILON {
+            panic!("Matrix is singular.")
+        }
+        if pivot != col {
+            for k in 0..n {
+                m.swap([pivot, k], [col, k]);
+            }
+            x.swap(pivot, col);
+        }
+        for row in (col + 1)..n {
+            let factor = m[[row, col]] / m[[col, col]];
+            if factor == 0.0 {
+                continue;
+            }
+            for k in col..n {
+                m[[row, k]] -= factor * m[[col, k]];
+            }
+            x[row] -= factor * x[col];
+        }
+    }
+    // Back substitution
+    for row in (0..n).rev() {
+        let mut sum = x[row];
+        for k in (row + 1)..n {
+            sum -= m[[row, k]] * x[k];
+        }
+        x[row] = sum / m[[row, row]];
+    }
+    x
+}
+
+
+/// Sparse matrix in compressed sparse row format.
+pub struct SparseMatrix {
+    n_rows: usize,
+    row_starts: Vec<usize>,
+    cols: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl SparseMatrix {
+    /// Copy the nonzero elements of a dense matrix.
+    pub fn from_dense(a: &Array2<f64>) -> SparseMatrix {
+        let mut row_starts = vec![0];
+        let mut cols = Vec::new();
+        let mut values = Vec::new();
+        for row in a.rows() {
+            for (j, &v) in row.iter().enumerate() {
+                if v != 0.0 {
+                    cols.push(j);
+                    values.push(v);
+                }
+            }
+            row_starts.push(cols.len());
+        }
+        SparseMatrix { n_rows: a.nrows(), row_starts, cols, values }
+    }
+
+    /// Number of nonzero elements.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Matrix-vector product, Ax.
+    pub fn dot(&self, x: &Array1<f64>) -> Array1<f64> {
+        Array1::from_shape_fn(self.n_rows, |i| {
+            (self.row_starts[i]..self.row_starts[i + 1])
+                .map(|k| self.values[k] * x[self.cols[k]])
+                .sum()
+        })
+    }
+}
+
+
+/// Solve Ax = b with the biconjugate gradient stabilized method.
+///
+/// Starts from x = b, which is a good guess when A is close to the identity.
+/// Returns an error if the residual hasn't converged after `max_iter`
+/// iterations, e.g., if the method broke down.
+pub fn bicgstab(
+    a: &SparseMatrix, b: &Array1<f64>, tol: f64, max_iter: usize
+) -> Result<Array1<f64>, NotConverged> {
+    let b_norm = b.dot(b).sqrt();
+    let mut x = b.clone();
+    let mut r = b - &a.dot(&x);
+    if b_norm == 0.0 || r.dot(&r).sqrt() <= tol * b_norm {
+        return Ok(x);
+    }
+    let r_hat = r.clone();
+    let (mut rho, mut alpha, mut omega) = (1.0, 1.0, 1.0);
+    let mut v = Array1::<f64>::zeros(b.len());
+    let mut p = Array1::<f64>::zeros(b.len());
+    for _ in 0..max_iter {
+        let rho_next = r_hat.dot(&r);
+        let beta = (rho_next / rho) * (alpha / omega);
+        rho = rho_next;
+        p = &r + &(beta * (&p - &(omega * &v)));
+        v = a.dot(&p);
+        alpha = rho / r_hat.dot(&v);
+        let s = &r - &(alpha * &v);
+        if s.dot(&s).sqrt() <= tol * b_norm {
+            return Ok(x + alpha * &p);
+        }
+        let t = a.dot(&s);
+        omega = t.dot(&s) / t.dot(&t);
+        x = x + alpha * &p + omega * &s;
+        r = &s - &(omega * &t);
+        if r.dot(&r).sqrt() <= tol * b_norm {
+            return Ok(x);
+        }
+    }
+    Err(NotConverged { iterations: max_iter, relative_residual: r.dot(&r).sqrt() / b_norm })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cars::RentalAgency;
+    use approx::assert_abs_diff_eq;
+    use ndarray::array;
+
+    #[test]
+    fn dense_solve_needs_pivoting() {
+        // Arrange
+        let a = array![[0.0, 2.0, 1.0], [1.0, 1.0, 0.0], [2.0, 0.0, 3.0]];
+        let b = array![7.0, 3.0, 11.0];
+        // Act
+        let x = solve_dense(&a, &b);
+        // Assert
+        assert_abs_diff_eq!(x, array![1.0, 2.0, 3.0], epsilon = 1e-12);
+    }
+
+    #[test]
+    fn bicgstab_matches_dense() {
+        // Arrange
+        let a = array![[4.0, -1.0, 0.0], [-1.0, 4.0, -2.0], [0.5, -1.0, 3.0]];
+        let b = array![2.0, 4.0, 10.0];
+        // Act
+        let x_dense = solve_dense(&a, &b);
+        let x_sparse = bicgstab(&SparseMatrix::from_dense(&a), &b, 1e-12, 100).unwrap();
+        let stopped = bicgstab(&SparseMatrix::from_dense(&a), &b, 1e-12, 1);
+        // Assert
+        assert_abs_diff_eq!(x_dense, x_sparse, epsilon = 1e-9);
+        assert!(matches!(stopped, Err(NotConverged { iterations: 1, .. })));
+    }
+
+    #[test]
+    fn transition_rows_sum_to_one() {
+        // Arrange
+        let cprobs = RentalAgency::new(3, 2.0, 1.0, 3, 1.0, 2.0, 1);
+        let model = TransitionModel::build(&cprobs);
+        let mut pi = Policy::build_from_model(&model);
+        pi.set_action(3, 0, 1);
+        // Act
+        let pmodel = PolicyModel::build(&model, &pi);
+        // Assert
+        for total in pmodel.p.sum_axis(ndarray::Axis(1)) {
+            assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn exact_values_match_iterative_evaluation() {
+        // Arrange
+        let cprobs = RentalAgency::new(5, 2.0, 1.0, 5, 1.0, 2.0, 2);
+        let model = TransitionModel::build(&cprobs);
+        let mut pi_exact = Policy::build_from_model(&model);
+        let mut pi_sparse = Policy::build_from_model(&model);
+        let mut pi_iter = Policy::build_from_model(&model);
+        // Act
+        evaluate_policy_exactly(&model, &mut pi_exact, LinearSolver::Dense).unwrap();
+        evaluate_policy_exactly(
+            &model, &mut pi_sparse,
+            LinearSolver::BiCgStab { tol: 1e-12, max_iter: 1000 }).unwrap();
+        crate::evaluate_policy(&model, &mut pi_iter, 1e-8);
+        // Assert
+        assert_abs_diff_eq!(pi_exact.value, pi_sparse.value, epsilon = 1e-6);
+        assert_abs_diff_eq!(pi_exact.value, pi_iter.value, epsilon = 1e-5);
+    }
+}
@@ -0,0 +1,320 @@
+//! Linear Programming Solver
+//!
+//! The optimal state values of a discounted MDP are the solution of the
+//! linear program
+//!
+//! minimize sum_s v(s)
+//! subject to v(s) >= r(s, a) + g * sum_s2 P(s2 | s, a) v(s2) for every
+//! valid action a in state s.
+//!
+//! The dual of this program has one variable x(s, a) >= 0 for each
+//! state-action pair and one equality constraint for each state,
+//!
+//! maximize sum_(s, a) r(s, a) x(s, a)
+//! subject to sum_a x(s2, a) - g * sum_(s, a) P(s2 | s, a) x(s, a) = 1.
+//!
+//! The dual variables x(s, a) are the discounted expected number of times
+//! that action a is taken in state s, starting with one "visit" to each
+//! state. This module solves the dual with the simplex method, which also
+//! yields the optimal values as the dual's shadow prices.
+
+use ndarray::{Array1, Array2, Array3};
+use crate::model::TransitionModel;
+use crate::policy::Policy;
+
+
+/// Reduced costs and pivots smaller than this are treated as zero.
+const EPSILON: f64 = 1e-9;
+
+
+/// Reasons the simplex method can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LpError {
+    /// No x satisfies the constraints.
+    Infeasible,
+    /// The objective can be increased without limit.
+    Unbounded,
+}
+
+
+/// Solution of a linear program in standard form.
+#[derive(Debug)]
+pub struct LpSolution {
+    /// Optimal primal variables
+    pub x: Array1<f64>,
+    /// Shadow price of each equality constraint
+    pub y: Array1<f64>,
+    /// Optimal objective value
+    pub objective: f64,
+    /// Number of simplex pivots, including phase one
+    pub pivots: u32,
+}
+
+
+/// Maximize c'x subject to Ax = b and x >= 0 with the two-phase simplex method.
+///
+/// Phase one starts from a basis of artificial variables and drives them
+/// to zero to find a feasible basis. Phase two then optimizes the objective.
+/// Uses Bland's rule to choose pivots, so degenerate problems can't cycle.
+pub fn simplex(
+    a: &Array2<f64>, b: &Array1<f64>, c: &Array1<f64>
+) -> Result<LpSolution, LpError> {
+    let (m, n) = a.dim();
+    // Tableau columns are the n variables, m artificials, and the rhs.
+    let rhs = n + m;
+    let mut t = Array2::<f64>::zeros((m, n + m + 1));
+    let mut flipped = vec![false; m];
+    for i in 0..m {
+        // Artificial variables need a nonnegative rhs.
+        let sign = if b[i] < 0.0 { -1.0 } else { 1.0 };
+        flipped[i] = b[i] < 0.0;
+        for j in 0..n {
+            t[[i, j]] = sign * a[[i, j]];
+        }
+        t[[i, n + i]] = 1.0;
+        t[[i, rhs]] = sign * b[i];
+    }
+    let mut basis: Vec<usize> = (n..n + m).collect();
+    let mut pivots = 0;
+
+    // Phase one: maximize minus the sum of the artificial variables.
+    let mut phase1_cost = Array1::<f64>::zeros(n + m);
+    phase1_cost.slice_mut(ndarray::s![n..]).fill(-1.0);
+    pivots += optimize(&mut t, &mut basis, &phase1_cost, n + m)?;
+    let infeasibility: f64 = basis.iter().enumerate()
+        .filter(|(_, &j)| j >= n)
+        .map(|(i, _)| t[[i, rhs]])
+        .sum();
+    if infeasibility > EPSILON {
+        return Err(LpError::Infeasible);
+    }
+    // Pivot artificial variables that are still basic (at zero) out of the
+    // basis. Rows where that's impossible are redundant constraints.
+    for i in 0..m {
+        if basis[i] >= n {
+            if let Some(j) = (0..n).find(|&j| t[[i, j]].abs() > EPSILON) {
+                pivot(&mut t, &mut basis, i, j);
+                pivots += 1;
+            }
+        }
+    }
+
+    // Phase two: artificial variables may no longer enter the basis.
+    let mut cost = Array1::<f64>::zeros(n + m);
+    cost.slice_mut(ndarray::s![..n]).assign(c);
+    pivots += optimize(&mut t, &mut basis, &cost, n)?;
+
+    let mut x = Array1::<f64>::zeros(n);
+    for (i, &j) in basis.iter().enumerate() {
+        if j < n {
+            x[j] = t[[i, rhs]];
+        }
+    }
+    // The artificial columns hold the inverse of the basis matrix, so the
+    // shadow prices are y' = c_B' B^-1.
+    let mut y = Array1::<f64>::zeros(m);
+    for k in 0..m {
+        y[k] = basis.iter().enumerate()
+            .map(|(i, &j)| cost[j] * t[[i, n + k]])
+            .sum();
+        if flipped[k] {
+            y[k] = -y[k];
+        }
+    }
+    Ok(LpSolution { objective: c.dot(&x), x, y, pivots })
+}
+
+
+/// Pivot until no column less than `n_enter` has a positive reduced cost.
+///
+/// Returns the number of pivots.
+fn optimize(
+    t: &mut Array2<f64>, basis: &mut [usize], cost: &Array1<f64>,
+    n_enter: usize
+) -> Result<u32, LpError> {
+    let rhs = t.ncols() - 1;
+    let mut pivots = 0;
+    loop {
+        // Bland's rule: lowest numbered column with a positive reduced cost.
+        let entering = (0..n_enter).find(|&j| {
+            let z: f64 = basis.iter().enumerate()
+                .map(|(i, &bj)| cost[bj] * t[[i, j]])
+                .sum();
+            cost[j] - z > EPSILON
+        });
+        let Some(j) = entering else {
+            return Ok(pivots);
+        };
+        // Ratio test, breaking ties by lowest numbered basic variable.
+        let mut leaving: Option<(usize, f64)> = None;
+        for i in 0..t.nrows() {
+            if t[[i, j]] > EPSILON {
+                let ratio = t[[i, rhs]] / t[[i, j]];
+                let better = match leaving {
+                    None => true,
+                    Some((l, best)) => ratio < best - EPSILON
+                        || (ratio <= best + EPSILON && basis[i] < basis[l]),
+                };
+                if better {
+                    leaving = Some((i, ratio));
+                }
+            }
+        }
+        let Some((i, _)) = leaving else {
+            return Err(LpError::Unbounded);
+        };
+        pivot(t, basis, i, j);
+        pivots += 1;
+    }
+}
+
+
+/// Make column j basic in row i.
+fn pivot(t: &mut Array2<f64>, basis: &mut [usize], i: usize, j: usize) {
+    let p = t[[i, j]];
+    t.row_mut(i).mapv_inplace(|v| v / p);
+    let pivot_row = t.row(i).to_owned();
+    for k in 0..t.nrows() {
+        if k != i {
+            let factor = t[[k, j]];
+            if factor != 0.0 {
+                t.row_mut(k).scaled_add(-factor, &pivot_row);
+            }
+        }
+    }
+    basis[i] = j;
+}
+
+
+/// Optimal policy and occupancy measures from the linear program.
+pub struct MdpLpSolution {
+    /// Optimal policy, with state values from the LP and action values
+    /// for every valid action
+    pub policy: Policy,
+    /// Discounted expected number of times each action is taken in each
+    /// state, starting with one visit to every state. Indexes are
+    /// n1, n2, a + max_move.
+    pub occupancy: Array3<f64>,
+    /// Number of simplex pivots
+    pub pivots: u32,
+}
+
+impl MdpLpSolution {
+    /// Occupancy measure for a state and action.
+    pub fn get_occupancy(&self, n1: u8, n2: u8, a: i8) -> f64 {
+        let a_idx = (a + self.policy.max_move as i8) as usize;
+        self.occupancy[[n1 as usize, n2 as usize, a_idx]]
+    }
+
+    /// Show the nonzero occupancy measures in the terminal.
+    ///
+    /// Frequencies are normalized so they sum to 1.0 over all states and
+    /// actions.
+    pub fn show_occupancy(&self) {
+        let total: f64 = self.occupancy.sum();
+        println!("    state       action   frequency");
+        for (idx, &x) in self.occupancy.indexed_iter() {
+            if x > EPSILON {
+                let a = idx.2 as i8 - self.policy.max_move as i8;
+                println!("    ({:>2}, {:>2}) {:>9} {:>11.5}",
+                    idx.0, idx.1, a, x / total);
+            }
+        }
+    }
+}
+
+
+/// Find the optimal policy by solving the MDP's linear program.
+///
+/// Solves the dual program with the simplex method. The policy takes the
+/// action with the largest occupancy measure in each state.
+pub fn linear_program(model: &TransitionModel) -> Result<MdpLpSolution, LpError> {
+    let n_states = model.n_states();
+    let pairs: Vec<_> = model.actions.iter().enumerate()
+        .flat_map(|(i, ams)| ams.iter().map(move |am| (i, am)))
+        .collect();
+    let mut a_mat = Array2::<f64>::zeros((n_states, pairs.len()));
+    let mut c = Array1::<f64>::zeros(pairs.len());
+    for (k, (i, am)) in pairs.iter().enumerate() {
+        for (j, p) in am.next() {
+            a_mat[[j, k]] -= model.g * p;
+        }
+        a_mat[[*i, k]] += 1.0;
+        c[k] = am.reward;
+    }
+    let b = Array1::<f64>::ones(n_states);
+    let solution = simplex(&a_mat, &b, &c)?;
+
+    let mut pi = Policy::build_from_model(model);
+    let mut occupancy = Array3::<f64>::zeros(pi.action_value.dim());
+    let mut best = Array2::<f64>::from_elem(pi.value.dim(), -1.0);
+    let v = solution.y.as_slice().expect("Dual values are not contiguous.");
+    for (k, (i, am)) in pairs.iter().enumerate() {
+        let s = model.state(*i);
+        let (n1, n2) = (s.n1 as usize, s.n2 as usize);
+        let a_idx = (am.a + model.max_move as i8) as usize;
+        occupancy[[n1, n2, a_idx]] = solution.x[k];
+        pi.set_value(s.n1, s.n2, am.a, model.backup(am, v));
+        if solution.x[k] > best[[n1, n2]] {
+            best[[n1, n2]] = solution.x[k];
+            pi.set_action(s.n1, s.n2, am.a);
+        }
+    }
+    for (i, &v_s) in v.iter().enumerate() {
+        let s = model.state(i);
+        pi.value[[s.n1 as usize, s.n2 as usize]] = v_s;
+    }
+    Ok(MdpLpSolution { policy: pi, occupancy, pivots: solution.pivots })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cars::RentalAgency;
+    use approx::assert_abs_diff_eq;
+    use ndarray::array;
+
+    #[test]
+    fn simplex_small_problem() {
+        // Arrange: maximize 3x1 + 2x2 with x1 + x2 <= 4, x1 + 3x2 <= 6,
+        // written with slack variables x3 and x4.
+        let a = array![[1.0, 1.0, 1.0, 0.0], [1.0, 3.0, 0.0, 1.0]];
+        let b = array![4.0, 6.0];
+        let c = array![3.0, 2.0, 0.0, 0.0];
+        // Act
+        let sol = simplex(&a, &b, &c).unwrap();
+        // Assert
+        assert_abs_diff_eq!(sol.objective, 12.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(sol.x, array![4.0, 0.0, 0.0, 2.0], epsilon = 1e-9);
+        assert_abs_diff_eq!(sol.y, array![3.0, 0.0], epsilon = 1e-9);
+    }
+
+    #[test]
+    fn simplex_detects_infeasible_and_unbounded() {
+        let a = array![[1.0, 1.0], [1.0, 1.0]];
+        let b = array![1.0, 2.0];
+        let c = array![1.0, 0.0];
+        assert_eq!(simplex(&a, &b, &c).unwrap_err(), LpError::Infeasible);
+        let a = array![[1.0, -1.0]];
+        let b = array![1.0];
+        let c = array![1.0, 0.0];
+        assert_eq!(simplex(&a, &b, &c).unwrap_err(), LpError::Unbounded);
+    }
+
+    #[test]
+    fn linear_program_matches_policy_iteration() {
+        // Arrange
+        let cprobs = RentalAgency::new(5, 1.0, 2.0, 5, 2.0, 1.0, 2);
+        let model = TransitionModel::build(&cprobs);
+        // Act
+        let sol = linear_program(&model).unwrap();
+        let (pi, _) = crate::policy_iteration(&model, 1e-6);
+        // Assert
+        assert_eq!(sol.policy.policy, pi.policy);
+        assert_abs_diff_eq!(sol.policy.value, pi.value, epsilon = 1e-3);
+        // Total discounted visits is one per state divided by (1 - g).
+        assert_abs_diff_eq!(sol.occupancy.sum(), 36.0 / (1.0 - cprobs.g),
+            epsilon = 1e-6);
+    }
+}
@@ -1,11 +1,26 @@
 #![allow(unused)]
 
-use std::{future::poll_fn, path::PathBuf};
-use clap::{Parser, Subcommand};
+use std::{future::poll_fn, path::PathBuf, process, time::Instant};
+use clap::{Parser, Subcommand, ValueEnum};
 use config_file::FromConfigFile;
-use serde::Deserialize;
 
-use rustcar2::{cars::RentalAgency, policy, solver::State, learn};
+use rustcar2::{
+    cars::RentalAgency, config::{CarConfig, NetworkConfig}, policy,
+    average::relative_value_iteration,
+    calendar::{calendar_value_iteration, Calendar, CalendarModel},
+    solver::{State, StateIterator},
+    network::{network_value_iteration, NetworkModel, RentalNetwork},
+    oneway::{one_way_value_iteration, OneWayModel},
+    transit::{transit_value_iteration, TransitModel},
+    horizon::{backward_induction, read_terminal_values},
+    learning::{
+        policy_gap, td_control, write_learning_curves, LearningParams, LearningRate, TdMethod
+    },
+    montecarlo::{monte_carlo_es, MonteCarloParams, Visits},
+    linear::{evaluate_policy_exactly, LinearSolver}, lp::linear_program,
+    model::TransitionModel, policy_iteration, value_iteration,
+    modified_policy_iteration, THETA
+};
 
 
 /// Command line argument parser.
@@ -28,36 +43,184 @@ enum Commands {
     // Reward {n1: u8, n2: u8},
     // /// Solve for optimal policy
     Trace {s1_n1: u8, s1_n2: u8, s2_n1: u8, s2_n2: u8, a: i8, xt: u32},
-    Solve
+    /// Solve for optimal policy
+    Solve {
+        /// Solution method
+        #[arg(long, value_enum, default_value_t = Method::PolicyIteration)]
+        method: Method,
+        /// Stop iterating when no state value changes by more than this
+        #[arg(long, default_value_t = THETA)]
+        theta: f64,
+        /// Evaluation sweeps per improvement for modified policy iteration.
+        /// Evaluates each policy fully if omitted.
+        #[arg(long)]
+        eval_sweeps: Option<u32>,
+        /// Number of threads for each sweep. Uses all cores if omitted.
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Calculate exact state values for a policy
+    Evaluate {
+        /// CSV file with one row of actions per number of cars at location #1.
+        /// Evaluates the policy that never moves cars if omitted.
+        policy_path: Option<PathBuf>,
+        /// Linear system solver
+        #[arg(long, value_enum, default_value_t = SystemSolver::Dense)]
+        solver: SystemSolver,
+    },
+    /// Solve for one policy per day of a rental season with backward
+    /// induction, and write each day's action table to a CSV file.
+    Season {
+        /// Number of days in the season
+        days: usize,
+        /// CSV file with the value of each state after the last day, one
+        /// row per number of cars at location #1. Values are zero if omitted.
+        #[arg(long)]
+        terminal: Option<PathBuf>,
+        /// Discount rate, overrides the configuration's gamma. Can be 1.
+        #[arg(long)]
+        gamma: Option<f64>,
+        /// Directory for the daily action tables
+        #[arg(long, default_value = "season")]
+        out_dir: PathBuf,
+    },
+    /// Learn a policy from simulated days with tabular temporal-difference
+    /// control, and compare it to the dynamic programming solution.
+    Learn {
+        /// Learning methods, separated by commas. Each uses the same seed.
+        #[arg(long = "method", value_enum, value_delimiter = ',',
+            default_value = "q-learning")]
+        methods: Vec<LearningMethod>,
+        /// Number of training episodes
+        #[arg(long, default_value_t = 5000)]
+        episodes: u32,
+        /// Days in each episode
+        #[arg(long, default_value_t = 100)]
+        steps: u32,
+        /// Constant step size. Uses 1 / n^alpha_power for the nth visit to
+        /// a state and action if omitted.
+        #[arg(long)]
+        alpha: Option<f64>,
+        /// Power for the decaying step size
+        #[arg(long, default_value_t = 0.6)]
+        alpha_power: f64,
+        /// Exploration rate in the first episode
+        #[arg(long, default_value_t = 1.0)]
+        epsilon: f64,
+        /// Factor applied to the exploration rate after each episode
+        #[arg(long, default_value_t = 0.999)]
+        epsilon_decay: f64,
+        /// Smallest exploration rate
+        #[arg(long, default_value_t = 0.05)]
+        min_epsilon: f64,
+        /// Seed for the day simulator
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// CSV file for the reward earned in each episode, one column per
+        /// method
+        #[arg(long)]
+        curve: Option<PathBuf>,
+    },
+    /// Learn a policy from simulated episodes with Monte Carlo control with
+    /// exploring starts, and compare it to the dynamic programming solution.
+    MonteCarlo {
+        /// Average the returns after the first visit or every visit to a
+        /// state and action in each episode
+        #[arg(long, value_enum, default_value_t = VisitsArg::First)]
+        visits: VisitsArg,
+        /// Number of training episodes
+        #[arg(long, default_value_t = 5000)]
+        episodes: u32,
+        /// Days in each episode whose visits are averaged
+        #[arg(long, default_value_t = 50)]
+        steps: u32,
+        /// Episodes between convergence checkpoints
+        #[arg(long, default_value_t = 500)]
+        check_every: u32,
+        /// Seed for the day simulator
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// CSV file for the reward earned in each episode
+        #[arg(long)]
+        curve: Option<PathBuf>,
+    },
+    /// Solve for optimal transfers between any number of locations with
+    /// value iteration. The configuration file lists each location.
+    Network {
+        /// Stop iterating when no state value changes by more than this
+        #[arg(long, default_value_t = THETA)]
+        theta: f64,
+    },
+    /// Solve for one policy per phase of the configuration's demand
+    /// schedule, e.g., one per day of the week, with value iteration.
+    Calendar {
+        /// Stop iterating when no state value changes by more than this
+        #[arg(long, default_value_t = THETA)]
+        theta: f64,
+    },
+    /// Solve for the optimal policy with one-way rentals, with value
+    /// iteration. The configuration file has a [one_way] table.
+    OneWay {
+        /// Stop iterating when no state value changes by more than this
+        #[arg(long, default_value_t = THETA)]
+        theta: f64,
+    },
+    /// Solve for the optimal policy when moved cars spend the
+    /// configuration's transfer_days in transit, with value iteration.
+    Transit {
+        /// Stop iterating when no state value changes by more than this
+        #[arg(long, default_value_t = THETA)]
+        theta: f64,
+    }
+}
+
+/// Dynamic programming methods for the `solve` command.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+#[allow(clippy::enum_variant_names)]
+enum Method {
+    PolicyIteration,
+    ValueIteration,
+    ModifiedPolicyIteration,
+    LinearProgramming,
+    /// Maximize the long-run reward per day instead of the discounted sum
+    RelativeValueIteration,
+}
+
+/// Temporal-difference methods for the `learn` command.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LearningMethod {
+    QLearning,
+    Sarsa,
+    ExpectedSarsa,
+}
+
+/// Returns averaged by the `monte-carlo` command.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum VisitsArg {
+    First,
+    Every,
 }
 
-/// Hold information read form TOML configuration file.
-#[derive(Deserialize, Debug)]
-pub struct CarConfig {
-    pub max1: u8,
-    pub rent_mean1: f32,
-    pub return_mean1: f32,
-    pub max2: u8,
-    pub rent_mean2: f32,
-    pub return_mean2: f32,
-    pub max_move: u8,
-    pub gamma: f32
+/// Linear system solvers for the `evaluate` command.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SystemSolver {
+    Dense,
+    Bicgstab,
 }
 
 
 fn main() {
     let args = Args::parse();
-    let cprobs = get_carprobs_from_config(&args.config_path);
-
     match &args.command {
         Commands::Probs => {
-            cprobs.show_probs();
+            get_carprobs_from_config(&args.config_path).show_probs();
         }
         // Commands::Reward {n1, n2} => {
         //     let r = cprobs.calc_value_for_action(&State {n1: *n1, n2: *n2}, 0i8);
         //     println!("Expected Reward: {:.2}", r);
         // }
         Commands::Trace {s1_n1, s1_n2, s2_n1, s2_n2, a, xt  } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
             let s1 = State { n1: *s1_n1, n2: *s1_n2 };
             let s2 = State { n1: *s2_n1, n2: *s2_n2 };
             let (r, prob, oprobs) = cprobs.calc_reward_prob(&s1, &s2, *a, *xt);
@@ -65,20 +228,336 @@ fn main() {
                 println!("{:?}", oc);
             }
         }
-        Commands::Solve => { learn(cprobs) }
+        Commands::Solve { method, theta, eval_sweeps, threads } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
+            if let Some(n) = threads {
+                rayon::ThreadPoolBuilder::new().num_threads(*n).build_global()
+                    .expect("Unable to start thread pool.");
+            }
+            let start = Instant::now();
+            let model = build_model(&cprobs);
+            let (pi, conv) = match method {
+                Method::PolicyIteration => policy_iteration(&model, *theta),
+                Method::ValueIteration => value_iteration(&model, *theta),
+                Method::ModifiedPolicyIteration => modified_policy_iteration(
+                    &model, *eval_sweeps, *theta),
+                Method::LinearProgramming => {
+                    let sol = linear_program(&model)
+                        .expect("Unable to solve linear program.");
+                    println!("\nSolved with {:?} in {:.2?}: {} simplex pivots",
+                        method, start.elapsed(), sol.pivots);
+                    show_solution(&sol.policy);
+                    println!("\n=== Occupancy Measures ===");
+                    sol.show_occupancy();
+                    return;
+                }
+                Method::RelativeValueIteration => {
+                    let (avg, conv) = relative_value_iteration(&model, *theta)
+                        .unwrap_or_else(|err| {
+                            eprintln!("{err}");
+                            process::exit(1);
+                        });
+                    println!("\nSolved with {:?} in {:.2?}: {} sweeps",
+                        method, start.elapsed(), conv.sweeps);
+                    println!("\nGain: {:.2} per day", avg.gain);
+                    println!("\n=== Policy ===");
+                    avg.policy.show_policy();
+                    println!("\n=== Bias ===");
+                    avg.policy.show_values();
+                    return;
+                }
+            };
+            println!("\nSolved with {:?} in {:.2?}: {} sweeps, {} improvements",
+                method, start.elapsed(), conv.sweeps, conv.improvements);
+            show_solution(&pi);
+        }
+        Commands::Evaluate { policy_path, solver } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
+            let mut pi = policy::Policy::build_from_agency(&cprobs);
+            if let Some(path) = policy_path {
+                pi.read_actions_csv(path).unwrap_or_else(|err| {
+                    eprintln!("Unable to read policy file: {err}");
+                    process::exit(1);
+                });
+            }
+            for s in StateIterator::new(cprobs.max1, cprobs.max2) {
+                let a = pi.get_action(s.n1, s.n2);
+                if !cprobs.actions(&s).contains(&a) {
+                    eprintln!("Invalid policy: action {a} isn't valid for state {s}.");
+                    process::exit(1);
+                }
+            }
+            let solver = match solver {
+                SystemSolver::Dense => LinearSolver::Dense,
+                SystemSolver::Bicgstab =>
+                    LinearSolver::BiCgStab { tol: 1e-10, max_iter: 1000 },
+            };
+            evaluate_policy_exactly(&build_model(&cprobs), &mut pi, solver)
+                .unwrap_or_else(|err| {
+                    eprintln!("Unable to evaluate policy: {err}");
+                    process::exit(1);
+                });
+            show_solution(&pi);
+        }
+        Commands::Season { days, terminal, gamma, out_dir } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
+            let mut model = build_model(&cprobs);
+            if let Some(g) = gamma {
+                if !(0.0..=1.0).contains(g) {
+                    eprintln!("Invalid discount rate: {g}");
+                    process::exit(1);
+                }
+                model.g = *g;
+            }
+            let terminal = match terminal {
+                Some(path) => read_terminal_values(path, cprobs.max1, cprobs.max2)
+                    .expect("Unable to read terminal values file."),
+                None => vec![0.0; model.n_states()],
+            };
+            let start = Instant::now();
+            let season = backward_induction(&model, *days, &terminal);
+            println!("\nSolved {} days in {:.2?}", season.horizon(), start.elapsed());
+            season.write_actions_csv(out_dir).expect("Unable to write policy files.");
+            println!("Wrote daily policies to {}", out_dir.display());
+            if let Some(first) = season.days.first() {
+                println!("\n=== First Day ===");
+                show_solution(first);
+            }
+        }
+        Commands::Learn {
+            methods, episodes, steps, alpha, alpha_power, epsilon, epsilon_decay,
+            min_epsilon, seed, curve
+        } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
+            let params = LearningParams {
+                episodes: *episodes,
+                steps: *steps,
+                alpha: match alpha {
+                    Some(alpha) => LearningRate::Constant(*alpha),
+                    None => LearningRate::Polynomial(*alpha_power),
+                },
+                epsilon: *epsilon,
+                epsilon_decay: *epsilon_decay,
+                min_epsilon: *min_epsilon,
+                seed: *seed,
+            };
+            let model = build_model(&cprobs);
+            let (optimal, _) = value_iteration(&model, 1e-6);
+            let mut trainings = Vec::new();
+            for method in methods {
+                let method = match method {
+                    LearningMethod::QLearning => TdMethod::QLearning,
+                    LearningMethod::Sarsa => TdMethod::Sarsa,
+                    LearningMethod::ExpectedSarsa => TdMethod::ExpectedSarsa,
+                };
+                let start = Instant::now();
+                let training = td_control(&cprobs, &params, method);
+                println!("\nLearned with {:?} in {:.2?}: {} episodes of {} days",
+                    method, start.elapsed(), params.episodes, params.steps);
+                show_solution(&training.policy);
+                let gap = policy_gap(&model, &training.policy, &optimal, 1e-6);
+                println!("\n=== Gap to Dynamic Programming ===");
+                println!("States with different actions: {} of {}",
+                    gap.actions_differ, model.n_states());
+                println!("Largest value shortfall: {:.2}", gap.max_value_gap);
+                println!("Average value shortfall: {:.2}", gap.mean_value_gap);
+                trainings.push((method, training));
+            }
+            if let Some(path) = curve {
+                let curves: Vec<(&str, &[f64])> = trainings.iter()
+                    .map(|(method, t)| (method.name(), t.episode_rewards.as_slice()))
+                    .collect();
+                write_learning_curves(path, &curves)
+                    .expect("Unable to write learning curve file.");
+                println!("\nWrote learning curves to {}", path.display());
+            }
+        }
+        Commands::MonteCarlo { visits, episodes, steps, check_every, seed, curve } => {
+            let cprobs = get_carprobs_from_config(&args.config_path);
+            let params = MonteCarloParams {
+                episodes: *episodes,
+                steps: *steps,
+                visits: match visits {
+                    VisitsArg::First => Visits::First,
+                    VisitsArg::Every => Visits::Every,
+                },
+                check_every: *check_every,
+                seed: *seed,
+            };
+            let model = build_model(&cprobs);
+            let (optimal, _) = value_iteration(&model, 1e-6);
+            let start = Instant::now();
+            let (training, checkpoints) = monte_carlo_es(&cprobs, &params, Some(&optimal));
+            println!("\nLearned with {:?}-visit Monte Carlo in {:.2?}: {} episodes of {} days",
+                params.visits, start.elapsed(), params.episodes, params.steps);
+            show_solution(&training.policy);
+            println!("\n=== Convergence ===");
+            println!("{:>8} | {:>14} | {:>10}", "episode", "actions differ", "value RMSE");
+            for c in &checkpoints {
+                println!("{:>8} | {:>14} | {:>10.2}", c.episode, c.actions_differ, c.value_rmse);
+            }
+            let gap = policy_gap(&model, &training.policy, &optimal, 1e-6);
+            println!("\n=== Gap to Dynamic Programming ===");
+            println!("States with different actions: {} of {}",
+                gap.actions_differ, model.n_states());
+            println!("Largest value shortfall: {:.2}", gap.max_value_gap);
+            println!("Average value shortfall: {:.2}", gap.mean_value_gap);
+            if let Some(path) = curve {
+                write_learning_curves(path, &[("monte_carlo", &training.episode_rewards)])
+                    .expect("Unable to write learning curve file.");
+                println!("\nWrote learning curve to {}", path.display());
+            }
+        }
+        Commands::Network { theta } => solve_network(&args.config_path, *theta),
+        Commands::Calendar { theta } => solve_calendar(&args.config_path, *theta),
+        Commands::OneWay { theta } => solve_one_way(&args.config_path, *theta),
+        Commands::Transit { theta } => solve_transit(&args.config_path, *theta),
     }
 }
 
 
+/// Read a network configuration and show its optimal transfers.
+fn solve_network(config_path: &PathBuf, theta: f64) {
+    let config = NetworkConfig::from_config_file(config_path).unwrap_or_else(|err| {
+        eprintln!("Unable to read network configuration file: {err}");
+        process::exit(1);
+    });
+    println!("Calculating rental and return probabilities.");
+    let network = RentalNetwork::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let start = Instant::now();
+    println!("Building transition model.");
+    let model = NetworkModel::build(&network);
+    let (pi, conv) = network_value_iteration(&model, theta);
+    println!("\nSolved in {:.2?}: {} sweeps", start.elapsed(), conv.sweeps);
+    println!("\n=== Transfers ===");
+    pi.show(&network, &model);
+}
+
+
+/// Read a configuration with a demand schedule and show each phase's
+/// optimal policy.
+fn solve_calendar(config_path: &PathBuf, theta: f64) {
+    let config = read_car_config(config_path);
+    println!("Calculating rental and return probabilities.");
+    let calendar = Calendar::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let start = Instant::now();
+    println!("Building transition model.");
+    let model = CalendarModel::build(&calendar);
+    let (pi, conv) = calendar_value_iteration(&model, theta);
+    println!("\nSolved {} phases in {:.2?}: {} sweeps",
+        calendar.n_phases(), start.elapsed(), conv.sweeps);
+    pi.show(&calendar);
+}
+
+
+/// Read a one-way rental configuration and show its optimal policy.
+fn solve_one_way(config_path: &PathBuf, theta: f64) {
+    let config = read_car_config(config_path);
+    let Some(one_way) = &config.one_way else {
+        eprintln!("Invalid configuration: missing [one_way] table");
+        process::exit(1);
+    };
+    println!("Calculating rental probabilities.");
+    let agency = RentalAgency::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let duration = one_way.rental_duration().unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let start = Instant::now();
+    println!("Building transition model.");
+    let model = OneWayModel::build(&agency, one_way, &duration).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let (pi, conv) = one_way_value_iteration(&model, theta);
+    println!("\nSolved {} states in {:.2?}: {} sweeps",
+        model.n_states(), start.elapsed(), conv.sweeps);
+    println!("\n=== Policy ===");
+    pi.show(&model);
+}
+
+
+/// Read a configuration with moves that take days and show its optimal
+/// policy.
+fn solve_transit(config_path: &PathBuf, theta: f64) {
+    let config = read_car_config(config_path);
+    println!("Calculating rental and return probabilities.");
+    let agency = RentalAgency::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let start = Instant::now();
+    println!("Building transition model.");
+    let model = TransitModel::build(&agency, config.transfer_days).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    });
+    let (pi, conv) = transit_value_iteration(&model, theta);
+    println!("\nSolved {} states in {:.2?}: {} sweeps",
+        model.n_states(), start.elapsed(), conv.sweeps);
+    println!("\n=== Policy ===");
+    pi.show(&model);
+}
+
+
+/// Precalculate rewards and transition probabilities.
+fn build_model(cprobs: &RentalAgency) -> TransitionModel {
+    println!("Building transition model.");
+    TransitionModel::build(cprobs)
+}
+
+
+/// Print the policy and its state values.
+fn show_solution(pi: &policy::Policy) {
+    println!("\n=== Policy ===");
+    pi.show_policy();
+    println!("\n=== State Values ===");
+    pi.show_values();
+}
+
+
+/// Read a two-location configuration, or exit with the reason it can't be
+/// read.
+fn read_car_config(config_path: &PathBuf) -> CarConfig {
+    CarConfig::from_config_file(config_path).unwrap_or_else(|err| {
+        if NetworkConfig::from_config_file(config_path).is_ok() {
+            eprintln!("Network configurations are only solved by the network command.");
+        } else {
+            eprintln!("Unable to read configuration file: {err}");
+        }
+        process::exit(1);
+    })
+}
+
+
 fn get_carprobs_from_config(config_path: &PathBuf) -> RentalAgency {
     println!("Reading config file: {}", config_path.to_str()
         .expect("Involid file path."));
-    let config = CarConfig::from_config_file(config_path)
-        .expect("Unable to read configuration file.");
+    let config = read_car_config(config_path);
+    if config.one_way.is_some() {
+        eprintln!("One-way rentals are only solved by the one-way command.");
+        process::exit(1);
+    }
+    if config.transfer_days > 0 {
+        eprintln!("Transfers with lead time are only solved by the transit command.");
+        process::exit(1);
+    }
+    if !config.schedule.is_empty() {
+        eprintln!("Demand schedules are only solved by the calendar command.");
+        process::exit(1);
+    }
     println!("Calculating rental and return probabilities.");
-    let cprobs = rustcar2::cars::RentalAgency::new(
-        config.max1, config.rent_mean1, config.return_mean1,
-        config.max2, config.rent_mean2, config.return_mean2,
-        config.max_move);
-    cprobs
+    RentalAgency::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {err}");
+        process::exit(1);
+    })
 }
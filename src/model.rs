@@ -0,0 +1,212 @@
+//! Transition Model
+//!
+//! Every Bellman backup needs the expected reward and the next state
+//! distribution for a state and action. `RentalAgency::calc_value_for_action`
+//! recalculates these from the rental and return probabilities on every
+//! call. The `TransitionModel` calculates them once, for every state and
+//! valid action, and stores the next state distribution in sparse form so
+//! solvers can sweep the state space quickly.
+//!
+//! Once cars are moved, each location's next-morning inventory is
+//! independent of the other location's, so the next state distribution is
+//! stored as a product of independent factors, one per location. This keeps
+//! the model small for big lots. A model can also have a single factor that
+//! lists every next state, which is how correlated demand is stored.
+//!
+//! States are indexed in the same order as `StateIterator`, i.e.,
+//! index = n1 * (max2 + 1) + n2.
+
+use crate::cars::RentalAgency;
+use crate::solver::{State, StateIterator};
+
+
+/// Expected reward and next state probabilities for one action.
+/// 
+/// Each factor lists (index offset, probability) pairs. A next state's
+/// index is the sum of one offset from each factor, and its probability is
+/// the product of the corresponding probabilities.
+/// 
+/// The action is the number of cars moved from location #1 to location #2
+/// unless another action type is given.
+#[derive(Debug, Clone)]
+pub struct ActionModel<A = i8> {
+    /// Action
+    pub a: A,
+    /// Expected reward
+    pub reward: f64,
+    /// Independent factors of the next state distribution
+    pub factors: Vec<Vec<(usize, f64)>>,
+}
+
+impl<A> ActionModel<A> {
+    /// Index and probability of each possible next state.
+    pub fn next(&self) -> Vec<(usize, f64)> {
+        let mut next = vec![(0, 1.0)];
+        for factor in &self.factors {
+            next = next.iter()
+                .flat_map(|&(i, p)| factor.iter().map(move |&(j, q)| (i + j, p * q)))
+                .collect();
+        }
+        next
+    }
+
+    /// Expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn expected_value(&self, v: &[f64]) -> f64 {
+        expected_value(&self.factors, v, 0)
+    }
+}
+
+
+/// Precalculated rewards and transition probabilities for all states.
+pub struct TransitionModel {
+    /// Maximum number of cars that can be stored at location #1
+    pub max1: u8,
+    /// Maximum number of cars that can be stored at location #2
+    pub max2: u8,
+    /// Maximum number of cars that can be moved between locations
+    pub max_move: u8,
+    /// Discount rate
+    pub g: f64,
+    /// Models for each valid action. Indexes are state index, action order.
+    pub actions: Vec<Vec<ActionModel>>,
+}
+
+impl TransitionModel {
+    /// Calculate rewards and transition probabilities for every state and
+    /// valid action.
+    pub fn build(agency: &RentalAgency) -> TransitionModel {
+        let actions = StateIterator::new(agency.max1, agency.max2)
+            .map(|s| {
+                agency.actions(&s)
+                    .map(|a| TransitionModel::build_action(agency, &s, a))
+                    .collect()
+            })
+            .collect();
+        TransitionModel {
+            max1: agency.max1, max2: agency.max2, max_move: agency.max_move,
+            g: agency.g, actions
+        }
+    }
+
+    /// Combine the two locations' next-morning probabilities for an action.
+    ///
+    /// Uses a single factor from the joint next-morning table if demand is
+    /// correlated.
+    fn build_action(agency: &RentalAgency, s: &State, a: i8) -> ActionModel {
+        let n1 = (s.n1 as i8 - a) as usize;
+        let n2 = (s.n2 as i8 + a) as usize;
+        let width = agency.max2 as usize + 1;
+        let reward = agency.expected_reward(s, a);
+        if let Some(t) = &agency.t_joint {
+            let factor = t.row(n1 * width + n2).iter().enumerate()
+                .filter(|(_, p)| **p > 0.0)
+                .map(|(i, p)| (i, *p))
+                .collect();
+            return ActionModel { a, reward, factors: vec![factor] };
+        }
+        let factor1 = agency.t1.row(n1).iter().enumerate()
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(n, p)| (n * width, *p))
+            .collect();
+        let factor2 = agency.t2.row(n2).iter().enumerate()
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(n, p)| (n, *p))
+            .collect();
+        ActionModel { a, reward, factors: vec![factor1, factor2] }
+    }
+
+    /// Number of states.
+    pub fn n_states(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Position of a state in `StateIterator` order.
+    pub fn index(&self, s: &State) -> usize {
+        agency_index(self.max2, s)
+    }
+
+    /// State at a position in `StateIterator` order.
+    pub fn state(&self, i: usize) -> State {
+        let width = self.max2 as usize + 1;
+        State { n1: (i / width) as u8, n2: (i % width) as u8 }
+    }
+
+    /// Model for a state and action. Returns `None` if the action is invalid.
+    pub fn action(&self, i: usize, a: i8) -> Option<&ActionModel> {
+        self.actions[i].iter().find(|am| am.a == a)
+    }
+
+    /// Expected reward plus the discounted expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn backup(&self, am: &ActionModel, v: &[f64]) -> f64 {
+        am.reward + self.g * am.expected_value(v)
+    }
+}
+
+
+/// Expected value of the next state, summing over one factor at a time.
+fn expected_value(factors: &[Vec<(usize, f64)>], v: &[f64], offset: usize) -> f64 {
+    match factors.split_first() {
+        None => v[offset],
+        Some((factor, rest)) => factor.iter()
+            .map(|&(j, p)| p * expected_value(rest, v, offset + j))
+            .sum(),
+    }
+}
+
+
+/// Position of a state in `StateIterator` order.
+fn agency_index(max2: u8, s: &State) -> usize {
+    s.n1 as usize * (max2 as usize + 1) + s.n2 as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::JointConfig;
+    use crate::dist::{Distribution, JointDistribution};
+    use crate::policy::Policy;
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    #[test_case(0.0, false; "Without lost-sales penalty")]
+    #[test_case(4.0, false; "With lost-sales penalty")]
+    #[test_case(4.0, true; "With correlated demand")]
+    fn model_matches_calc_value_for_action(penalty: f64, correlated: bool) {
+        // Arrange
+        let mut cprobs = if correlated {
+            let joint = JointDistribution::from_config("rent_joint",
+                &JointConfig::BivariatePoisson { mean1: 2.0, mean2: 1.0, common_mean: 0.5 })
+                .unwrap();
+            RentalAgency::with_joint_rentals(
+                3, &joint, Distribution::poisson(1.0), 3, Distribution::poisson(2.0), 1)
+        } else {
+            RentalAgency::new(3, 2.0, 1.0, 3, 1.0, 2.0, 1)
+        };
+        cprobs.lost_sale_penalty = penalty;
+        let mut pi = Policy::build_from_agency(&cprobs);
+        for (i, v) in pi.value.iter_mut().enumerate() {
+            *v = i as f64;
+        }
+        // Act
+        let model = TransitionModel::build(&cprobs);
+        // Assert
+        for s in StateIterator::new(3, 3) {
+            let i = model.index(&s);
+            assert_eq!(model.state(i), s);
+            assert_eq!(model.actions[i].len(), cprobs.actions(&s).count());
+            for am in &model.actions[i] {
+                let total: f64 = am.next().iter().map(|(_, p)| p).sum();
+                assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+                assert_abs_diff_eq!(
+                    model.backup(am, pi.value.as_slice().unwrap()),
+                    cprobs.calc_value_for_action(&s, am.a, &pi),
+                    epsilon = 1e-9);
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Monte Carlo Control
+//!
+//! Monte Carlo methods estimate an action value by averaging the
+//! discounted returns that followed visits to the state and action in
+//! simulated episodes, with no bootstrapping from other estimates. With
+//! exploring starts, each episode starts from a random state with a random
+//! valid action and then follows the current policy, so every state and
+//! action keeps being tried without ε-greedy exploration. After each
+//! episode, the policy is made greedy in the states it visited.
+//!
+//! First-visit Monte Carlo averages the return after the first visit to a
+//! state and action in each episode. Every-visit Monte Carlo averages the
+//! returns after every visit. The averages include returns from earlier,
+//! worse policies, so estimates approach the optimal action values slowly,
+//! and the `Checkpoint`s show how far training has come.
+//!
+//! The car rental problem never ends, so episodes are cut off. Each episode
+//! runs for `steps` days plus enough extra days for the discount to shrink
+//! rewards to a thousandth of their value, and only visits in the first
+//! `steps` days are averaged, so their returns are nearly complete.
+
+use crate::cars::RentalAgency;
+use crate::learning::{greedy_action, make_greedy, Training};
+use crate::policy::Policy;
+use crate::sim::DaySimulator;
+use crate::solver::{State, StateIterator};
+use rand::seq::IteratorRandom;
+
+
+/// Returns that are averaged in each episode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visits {
+    /// Only the first visit to each state and action
+    First,
+    /// Every visit to each state and action
+    Every,
+}
+
+
+/// Training settings for Monte Carlo control.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonteCarloParams {
+    /// Number of episodes
+    pub episodes: u32,
+    /// Days in each episode whose visits are averaged
+    pub steps: u32,
+    /// First-visit or every-visit averaging
+    pub visits: Visits,
+    /// Episodes between convergence checkpoints
+    pub check_every: u32,
+    /// Seed for the simulator and exploring starts
+    pub seed: u64,
+}
+
+impl Default for MonteCarloParams {
+    fn default() -> MonteCarloParams {
+        MonteCarloParams {
+            episodes: 5_000, steps: 50, visits: Visits::First, check_every: 500, seed: 0
+        }
+    }
+}
+
+
+/// Distance from the learned policy to the optimal policy during training.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    /// Episodes completed
+    pub episode: u32,
+    /// Number of states where the learned policy's action isn't optimal
+    pub actions_differ: usize,
+    /// Root mean square difference between the learned and optimal state
+    /// values
+    pub value_rmse: f64,
+}
+
+
+/// Learn action values with Monte Carlo control with exploring starts.
+///
+/// Returns are averaged into `Policy::action_value`, and the episode
+/// rewards are the total undiscounted reward in each episode's first
+/// `steps` days. If an optimal policy is given, e.g., from value
+/// iteration, the learned policy is compared to it every `check_every`
+/// episodes.
+pub fn monte_carlo_es(
+    agency: &RentalAgency, params: &MonteCarloParams, optimal: Option<&Policy>
+) -> (Training, Vec<Checkpoint>) {
+    let mut pi = Policy::build_from_agency(agency);
+    let mut visits = ndarray::Array3::<u32>::zeros(pi.action_value.dim());
+    let mut sim = DaySimulator::new(agency, params.seed);
+    let steps = params.steps as usize;
+    let days = steps + tail_days(agency.g);
+    let mut episode_rewards = Vec::with_capacity(params.episodes as usize);
+    let mut checkpoints = Vec::new();
+    for episode in 1..=params.episodes {
+        // Exploring start, then follow the policy.
+        let mut s = sim.random_state();
+        let mut a = agency.actions(&s).choose(sim.rng()).expect("No valid actions.");
+        let mut trajectory = Vec::with_capacity(days);
+        for _ in 0..days {
+            let (r, s2) = sim.step(&s, a);
+            trajectory.push((s.n1, s.n2, a, r));
+            a = pi.get_action(s2.n1, s2.n2);
+            s = s2;
+        }
+        let rewards: Vec<f64> = trajectory.iter().map(|&(.., r)| r).collect();
+        let returns = discounted_returns(&rewards, agency.g);
+        let mut seen = ndarray::Array3::<bool>::from_elem(pi.action_value.dim(), false);
+        for (&(n1, n2, a, _), g) in trajectory[..steps].iter().zip(returns) {
+            let idx = [n1 as usize, n2 as usize, (a + pi.max_move as i8) as usize];
+            if params.visits == Visits::First && seen[idx] {
+                continue;
+            }
+            seen[idx] = true;
+            visits[idx] += 1;
+            let q = pi.get_value(n1, n2, a);
+            pi.set_value(n1, n2, a, q + (g - q) / visits[idx] as f64);
+        }
+        for &(n1, n2, ..) in &trajectory[..steps] {
+            let a = greedy_action(&pi, agency, &State { n1, n2 });
+            pi.set_action(n1, n2, a);
+        }
+        episode_rewards.push(rewards[..steps].iter().sum());
+        if let Some(optimal) = optimal {
+            if params.check_every > 0 && episode % params.check_every == 0 {
+                checkpoints.push(checkpoint(episode, &pi, optimal));
+            }
+        }
+    }
+    make_greedy(&mut pi, agency);
+    (Training { policy: pi, episode_rewards }, checkpoints)
+}
+
+
+/// Compare a learned policy's actions and state values to the optimal
+/// policy's.
+fn checkpoint(episode: u32, pi: &Policy, optimal: &Policy) -> Checkpoint {
+    let mut actions_differ = 0;
+    let mut squares = 0.0;
+    let states = StateIterator::new(pi.max1, pi.max2);
+    let n_states = (pi.max1 as usize + 1) * (pi.max2 as usize + 1);
+    for s in states {
+        if pi.get_action(s.n1, s.n2) != optimal.get_action(s.n1, s.n2) {
+            actions_differ += 1;
+        }
+        let diff = pi.get_state_value(s.n1, s.n2) - optimal.get_state_value(s.n1, s.n2);
+        squares += diff * diff;
+    }
+    Checkpoint { episode, actions_differ, value_rmse: (squares / n_states as f64).sqrt() }
+}
+
+
+/// Discounted return following each day, G_t = r_t + g G_(t+1).
+fn discounted_returns(rewards: &[f64], g: f64) -> Vec<f64> {
+    let mut returns = vec![0.0; rewards.len()];
+    let mut total = 0.0;
+    for (t, r) in rewards.iter().enumerate().rev() {
+        total = r + g * total;
+        returns[t] = total;
+    }
+    returns
+}
+
+
+/// Extra days needed for the discount to shrink rewards to a thousandth
+/// of their value.
+fn tail_days(g: f64) -> usize {
+    if g <= 0.0 {
+        return 0;
+    }
+    (1e-3f64.ln() / g.ln()).ceil() as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::learning::policy_gap;
+    use crate::model::TransitionModel;
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    #[test]
+    fn returns_are_discounted_from_the_end() {
+        // Act
+        let returns = discounted_returns(&[1.0, 2.0, 4.0], 0.5);
+        // Assert
+        assert_eq!(returns, vec![3.0, 4.0, 4.0]);
+        assert_eq!(tail_days(0.9), 66);
+        assert_eq!(tail_days(0.0), 0);
+    }
+
+    #[test_case(Visits::First; "First visit")]
+    #[test_case(Visits::Every; "Every visit")]
+    fn monte_carlo_converges_to_the_optimal_policy(visits: Visits) {
+        // Arrange
+        let mut agency = RentalAgency::new(4, 3.0, 1.0, 4, 1.0, 3.0, 2);
+        agency.g = 0.8;
+        let model = TransitionModel::build(&agency);
+        let (optimal, _) = crate::value_iteration(&model, 1e-6);
+        let params = MonteCarloParams {
+            episodes: 10_000, steps: 20, visits, check_every: 1_000, ..Default::default()
+        };
+        // Act
+        let (training, checkpoints) = monte_carlo_es(&agency, &params, Some(&optimal));
+        let gap = policy_gap(&model, &training.policy, &optimal, 1e-6);
+        // Assert
+        assert_eq!(checkpoints.len(), 10);
+        assert_eq!(checkpoints[9].episode, 10_000);
+        assert!(checkpoints[9].value_rmse < checkpoints[0].value_rmse, "{checkpoints:?}");
+        assert!(checkpoints[9].value_rmse < 0.6 * checkpoints[0].value_rmse,
+            "{checkpoints:?}");
+        assert!(gap.mean_value_gap < 0.01 * optimal.get_state_value(2, 2), "{gap:?}");
+        assert_eq!(training.episode_rewards.len(), 10_000);
+    }
+
+    #[test]
+    fn same_seed_learns_the_same_policy() {
+        // Arrange
+        let agency = RentalAgency::new(4, 3.0, 1.0, 4, 1.0, 3.0, 2);
+        let params = MonteCarloParams { episodes: 20, seed: 4, ..Default::default() };
+        // Act
+        let (t1, checkpoints) = monte_carlo_es(&agency, &params, None);
+        let (t2, _) = monte_carlo_es(&agency, &params, None);
+        // Assert
+        assert!(checkpoints.is_empty());
+        assert_eq!(t1.policy.action_value, t2.policy.action_value);
+        assert_eq!(t1.episode_rewards, t2.episode_rewards);
+        for s in StateIterator::new(4, 4) {
+            let q = t1.policy.get_value(s.n1, s.n2, t1.policy.get_action(s.n1, s.n2));
+            assert_abs_diff_eq!(t1.policy.get_state_value(s.n1, s.n2), q);
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Rental Network
+//!
+//! `RentalAgency` models exactly two locations. A `RentalNetwork` has any
+//! number of locations, each with its own lot size, rental and return
+//! means, and rental price. The state is the inventory vector, i.e., the
+//! number of cars at each location, and an action is a transfer matrix
+//! with the number of cars moved overnight from each location to each
+//! other location. The total number of cars moved is limited by the truck
+//! capacity.
+//!
+//! Every car moved costs the same, so only the inventory after moving cars
+//! matters. Each valid post-move inventory gets one action, the transfer
+//! matrix that moves the fewest cars.
+//!
+//! A network is a separate model with its own rewards and transitions, and
+//! only supports independent demand and the standard reward variant. The
+//! two-location `RentalAgency`, its `TransitionModel`, and every solver and
+//! option built on them, e.g., Exercise 4.7, correlated demand, one-way
+//! rentals, and transfers with lead time, don't use it. Options added to
+//! one model aren't available in the other. `RentalNetwork::from_agency`
+//! builds the equivalent network for a two-location agency that only uses
+//! options networks support, so results from the two models can be
+//! compared.
+//!
+//! States are indexed in row-major order of the inventory vector, so a
+//! two-location network uses the same state indexes as `TransitionModel`.
+
+use ndarray::{Array1, Array2};
+use crate::cars::{RentalAgency, RewardVariant};
+use crate::config::{ConfigError, NetworkConfig};
+use crate::dist::Distribution;
+use crate::model::ActionModel;
+use crate::Convergence;
+
+
+/// Number of cars moved from each location (row) to each location (column).
+pub type Transfer = Array2<u8>;
+
+
+/// One rental location and its precalculated probabilities.
+pub struct Location {
+    /// Maximum number of cars that can be stored at the location
+    pub max: u8,
+    /// Expected number of cars rented each day
+    pub rent_mean: f32,
+    /// Expected number of cars returned each day
+    pub return_mean: f32,
+    /// Revenue for each car rented
+    pub price: f64,
+    /// Next-morning probs. Indexes: cars on lot after moves, cars on lot next morning
+    pub t: Array2<f64>,
+    /// Expected rentals. Index: cars on lot after moves
+    pub e: Array1<f64>,
+    /// Expected customers turned away. Index: cars on lot after moves
+    pub u: Array1<f64>,
+}
+
+impl Location {
+    /// Create a new location with pre-calculated probabilities.
+    /// 
+    /// Rentals and returns are Poisson with the given means.
+    pub fn new(max: u8, rent_mean: f32, return_mean: f32, price: f64) -> Location {
+        Location::with_distributions(
+            max, &Distribution::poisson(f64::from(rent_mean)),
+            &Distribution::poisson(f64::from(return_mean)), price)
+    }
+
+    /// Create a new location with any rental and return distributions.
+    pub fn with_distributions(
+        max: u8, rent_dist: &Distribution, return_dist: &Distribution, price: f64
+    ) -> Location {
+        let x = RentalAgency::calc_rent_probs(rent_dist, max);
+        let y = RentalAgency::calc_return_probs(return_dist, max);
+        let (t, e) = RentalAgency::calc_next_probs(&x, &y);
+        let u = RentalAgency::calc_unmet_demand(rent_dist, max);
+        Location {
+            max, rent_mean: rent_dist.mean() as f32,
+            return_mean: return_dist.mean() as f32, price, t, e, u
+        }
+    }
+}
+
+
+/// Any number of rental locations sharing one truck.
+pub struct RentalNetwork {
+    /// Locations, in state vector order
+    pub locations: Vec<Location>,
+    /// Maximum number of cars moved between all locations each night
+    pub truck_capacity: u8,
+    /// Cost for each car moved between locations
+    pub move_cost: f64,
+    /// Goodwill cost for each customer turned away because the lot is empty
+    pub lost_sale_penalty: f64,
+    /// Discount rate
+    pub g: f64,
+}
+
+impl RentalNetwork {
+    /// Create a network from configuration parameters.
+    ///
+    /// Returns an error instead of calculating probabilities if any of the
+    /// parameters are invalid.
+    pub fn from_config(config: &NetworkConfig) -> Result<RentalNetwork, ConfigError> {
+        config.validate()?;
+        let mut locations = Vec::new();
+        for loc in &config.locations {
+            let [rent_dist, return_dist] = loc.distributions()?;
+            locations.push(Location::with_distributions(
+                loc.max, &rent_dist, &return_dist, loc.rental_price));
+        }
+        Ok(RentalNetwork {
+            locations,
+            truck_capacity: config.truck_capacity,
+            move_cost: config.move_cost,
+            lost_sale_penalty: config.lost_sale_penalty,
+            g: config.gamma,
+        })
+    }
+
+    /// Create a two-location network with the same states, rewards, and
+    /// transitions as a `RentalAgency`, e.g., to check one model against
+    /// the other. The transfer matrix holds `a` cars from location #1 to
+    /// location #2 (or `-a` cars the other way), and the truck capacity is
+    /// `max_move`.
+    ///
+    /// Returns an error if the agency uses the Exercise 4.7 reward variant
+    /// or correlated demand, which networks don't support.
+    pub fn from_agency(agency: &RentalAgency) -> Result<RentalNetwork, ConfigError> {
+        if agency.variant != RewardVariant::Standard {
+            return Err(ConfigError::Network(
+                "only the standard reward variant is supported".to_string()));
+        }
+        if agency.rent_joint.is_some() {
+            return Err(ConfigError::Network(
+                "only independent demand is supported".to_string()));
+        }
+        let locations = vec![
+            Location {
+                max: agency.max1, rent_mean: agency.rent_mean1,
+                return_mean: agency.return_mean1, price: agency.price1,
+                t: agency.t1.clone(), e: agency.e1.clone(), u: agency.u1.clone(),
+            },
+            Location {
+                max: agency.max2, rent_mean: agency.rent_mean2,
+                return_mean: agency.return_mean2, price: agency.price2,
+                t: agency.t2.clone(), e: agency.e2.clone(), u: agency.u2.clone(),
+            },
+        ];
+        Ok(RentalNetwork {
+            locations,
+            truck_capacity: agency.max_move,
+            move_cost: agency.move_cost,
+            lost_sale_penalty: agency.lost_sale_penalty,
+            g: agency.g,
+        })
+    }
+
+    /// Number of states.
+    pub fn n_states(&self) -> usize {
+        self.locations.iter().map(|loc| loc.max as usize + 1).product()
+    }
+
+    /// Amount the state index changes when a location gains one car.
+    fn strides(&self) -> Vec<usize> {
+        let mut strides = vec![1; self.locations.len()];
+        for k in (0..self.locations.len().saturating_sub(1)).rev() {
+            strides[k] = strides[k + 1] * (self.locations[k + 1].max as usize + 1);
+        }
+        strides
+    }
+
+    /// Position of an inventory vector in state index order.
+    pub fn index(&self, inventory: &[u8]) -> usize {
+        inventory.iter().zip(self.strides())
+            .map(|(&n, stride)| n as usize * stride)
+            .sum()
+    }
+
+    /// Inventory vector at a position in state index order.
+    pub fn inventory(&self, i: usize) -> Vec<u8> {
+        self.strides().iter().zip(&self.locations)
+            .map(|(stride, loc)| ((i / stride) % (loc.max as usize + 1)) as u8)
+            .collect()
+    }
+
+    /// Valid transfer matrices for an inventory.
+    ///
+    /// Can't send more cars than what's on a lot, fill a lot past its max,
+    /// or move more cars than the truck capacity. Returns one transfer for
+    /// each reachable post-move inventory, ordered by the number of cars
+    /// moved, so not moving any cars is always first.
+    pub fn transfers(&self, inventory: &[u8]) -> Vec<Transfer> {
+        let mut changes = Vec::new();
+        self.add_changes(inventory, &mut Vec::new(), 0, 0, &mut changes);
+        changes.sort_by_key(|change| cars_moved(change));
+        changes.iter().map(|change| transfer_for_change(change)).collect()
+    }
+
+    /// Recursively list the valid changes in each location's inventory.
+    ///
+    /// `change` holds the changes for the locations before location k.
+    /// `sent` and `received` are the cars leaving and arriving so far.
+    fn add_changes(
+        &self, inventory: &[u8], change: &mut Vec<i16>, sent: i16, received: i16,
+        changes: &mut Vec<Vec<i16>>
+    ) {
+        let k = change.len();
+        let capacity = self.truck_capacity as i16;
+        let n = inventory[k] as i16;
+        let room = self.locations[k].max as i16 - n;
+        if k == inventory.len() - 1 {
+            // The last location balances the others.
+            let d = sent - received;
+            // Cars sent and received balance, so both equal the cars moved.
+            if -n <= d && d <= room && sent.max(received) <= capacity {
+                change.push(d);
+                changes.push(change.clone());
+                change.pop();
+            }
+            return;
+        }
+        for d in -n.min(capacity - sent)..=room.min(capacity - received) {
+            change.push(d);
+            let (s, r) = if d < 0 { (sent - d, received) } else { (sent, received + d) };
+            self.add_changes(inventory, change, s, r, changes);
+            change.pop();
+        }
+    }
+
+    /// Number of cars at each location after moving cars.
+    pub fn after_moves(inventory: &[u8], transfer: &Transfer) -> Vec<u8> {
+        inventory.iter().enumerate()
+            .map(|(k, &n)| {
+                let sent: u32 = transfer.row(k).iter().map(|&c| c as u32).sum();
+                let received: u32 = transfer.column(k).iter().map(|&c| c as u32).sum();
+                (n as u32 + received - sent) as u8
+            })
+            .collect()
+    }
+
+    /// Calculate the expected reward given the inventory and transfer.
+    ///
+    /// Expected rental revenue minus the moving cost and the expected
+    /// goodwill cost of customers turned away.
+    pub fn expected_reward(&self, inventory: &[u8], transfer: &Transfer) -> f64 {
+        let moved: u32 = transfer.iter().map(|&c| c as u32).sum();
+        let revenue: f64 = RentalNetwork::after_moves(inventory, transfer).iter()
+            .zip(&self.locations)
+            .map(|(&m, loc)| {
+                loc.e[m as usize] * loc.price
+                    - self.lost_sale_penalty * loc.u[m as usize]
+            })
+            .sum();
+        revenue - self.move_cost * moved as f64
+    }
+}
+
+
+/// Total number of cars moved by an inventory change.
+fn cars_moved(change: &[i16]) -> i16 {
+    change.iter().filter(|&&d| d > 0).sum()
+}
+
+
+/// Transfer matrix that makes an inventory change with the fewest moves.
+///
+/// Matches senders to receivers in location order.
+fn transfer_for_change(change: &[i16]) -> Transfer {
+    let n = change.len();
+    let mut transfer = Transfer::zeros((n, n));
+    let mut surplus: Vec<(usize, i16)> = change.iter().enumerate()
+        .filter(|(_, &d)| d < 0).map(|(k, &d)| (k, -d)).collect();
+    let mut need: Vec<(usize, i16)> = change.iter().enumerate()
+        .filter(|(_, &d)| d > 0).map(|(k, &d)| (k, d)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < surplus.len() && j < need.len() {
+        let cars = surplus[i].1.min(need[j].1);
+        transfer[[surplus[i].0, need[j].0]] += cars as u8;
+        surplus[i].1 -= cars;
+        need[j].1 -= cars;
+        if surplus[i].1 == 0 {
+            i += 1;
+        }
+        if need[j].1 == 0 {
+            j += 1;
+        }
+    }
+    transfer
+}
+
+
+/// Precalculated rewards and transition probabilities for a network.
+pub struct NetworkModel {
+    /// Discount rate
+    pub g: f64,
+    /// Models for each valid transfer. Indexes are state index, action order.
+    pub actions: Vec<Vec<ActionModel<Transfer>>>,
+}
+
+impl NetworkModel {
+    /// Calculate rewards and transition probabilities for every state and
+    /// valid transfer.
+    ///
+    /// Each location contributes one factor to the next state distribution.
+    pub fn build(network: &RentalNetwork) -> NetworkModel {
+        let strides = network.strides();
+        let actions = (0..network.n_states())
+            .map(|i| {
+                let inventory = network.inventory(i);
+                network.transfers(&inventory).into_iter()
+                    .map(|transfer| {
+                        let after = RentalNetwork::after_moves(&inventory, &transfer);
+                        let factors = after.iter().zip(&network.locations)
+                            .zip(&strides)
+                            .map(|((&m, loc), &stride)| {
+                                loc.t.row(m as usize).iter().enumerate()
+                                    .filter(|(_, p)| **p > 0.0)
+                                    .map(|(n, p)| (n * stride, *p))
+                                    .collect()
+                            })
+                            .collect();
+                        let reward = network.expected_reward(&inventory, &transfer);
+                        ActionModel { a: transfer, reward, factors }
+                    })
+                    .collect()
+            })
+            .collect();
+        NetworkModel { g: network.g, actions }
+    }
+
+    /// Number of states.
+    pub fn n_states(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Expected reward plus the discounted expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn backup(&self, am: &ActionModel<Transfer>, v: &[f64]) -> f64 {
+        am.reward + self.g * am.expected_value(v)
+    }
+}
+
+
+/// Transfer chosen for each state of a network.
+pub struct NetworkPolicy {
+    /// Position of the chosen transfer in each state's action list
+    pub actions: Vec<usize>,
+    /// State values, in state index order
+    pub values: Vec<f64>,
+}
+
+impl NetworkPolicy {
+    /// Transfer matrix chosen for a state.
+    pub fn transfer<'a>(&self, model: &'a NetworkModel, i: usize) -> &'a Transfer {
+        &model.actions[i][self.actions[i]].a
+    }
+
+    /// Show each state's transfers and value in the terminal.
+    ///
+    /// Transfers are listed as "from->to:cars", with locations numbered
+    /// from 1. States that don't move cars show a dash.
+    pub fn show(&self, network: &RentalNetwork, model: &NetworkModel) {
+        println!("{:>20} | {:>10} | transfers", "inventory", "value");
+        for i in 0..model.n_states() {
+            let moves: Vec<String> = self.transfer(model, i).indexed_iter()
+                .filter(|(_, &c)| c > 0)
+                .map(|((from, to), c)| format!("{}->{}:{}", from + 1, to + 1, c))
+                .collect();
+            let moves = if moves.is_empty() { String::from("-") } else { moves.join(" ") };
+            println!("{:>20} | {:>10.2} | {}",
+                format!("{:?}", network.inventory(i)), self.values[i], moves);
+        }
+    }
+}
+
+
+/// Find the optimal network policy with value iteration.
+///
+/// Ties keep the first transfer, which moves the fewest cars.
+pub fn network_value_iteration(
+    model: &NetworkModel, theta: f64
+) -> (NetworkPolicy, Convergence) {
+    let (actions, values, conv) =
+        crate::table_value_iteration(&model.actions, model.g, theta);
+    (NetworkPolicy { actions, values }, conv)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TransitionModel;
+    use approx::assert_abs_diff_eq;
+
+    fn network() -> RentalNetwork {
+        RentalNetwork {
+            locations: vec![
+                Location::new(3, 1.0, 2.0, 10.0),
+                Location::new(2, 2.0, 1.0, 12.0),
+                Location::new(4, 1.5, 1.5, 8.0),
+            ],
+            truck_capacity: 2, move_cost: 2.0, lost_sale_penalty: 1.0, g: 0.9,
+        }
+    }
+
+    #[test]
+    fn inventories_round_trip() {
+        // Arrange
+        let net = network();
+        // Assert
+        assert_eq!(net.n_states(), 4 * 3 * 5);
+        assert_eq!(net.index(&[1, 2, 3]), 15 + 2 * 5 + 3);
+        for i in 0..net.n_states() {
+            assert_eq!(net.index(&net.inventory(i)), i);
+        }
+    }
+
+    #[test]
+    fn transfers_respect_lots_and_truck() {
+        // Arrange
+        let net = network();
+        let inventory = [3, 0, 1];
+        // Act
+        let transfers = net.transfers(&inventory);
+        // Assert
+        assert_eq!(transfers[0], Transfer::zeros((3, 3)));
+        let mut seen = Vec::new();
+        for transfer in &transfers {
+            let moved: u32 = transfer.iter().map(|&c| c as u32).sum();
+            assert!(moved <= 2);
+            let after = RentalNetwork::after_moves(&inventory, transfer);
+            assert_eq!(after.iter().map(|&n| n as u32).sum::<u32>(), 4);
+            for (m, loc) in after.iter().zip(&net.locations) {
+                assert!(*m <= loc.max);
+            }
+            assert!(!seen.contains(&after));
+            seen.push(after);
+        }
+        // Location #1 can send up to two cars to #2 and #3 in any split
+        // (six inventories), and location #3 can send its car to #2 alone
+        // or with one car from location #1 (two more).
+        assert_eq!(transfers.len(), 8);
+    }
+
+    #[test]
+    fn two_location_network_matches_agency() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(5, 3.0, 1.0, 4, 1.0, 2.0, 2);
+        cprobs.lost_sale_penalty = 2.0;
+        let model = TransitionModel::build(&cprobs);
+        let net = RentalNetwork::from_agency(&cprobs).unwrap();
+        // Act
+        let (pi, _) = crate::value_iteration(&model, 1e-6);
+        let net_model = NetworkModel::build(&net);
+        let (net_pi, _) = network_value_iteration(&net_model, 1e-6);
+        // Assert
+        assert_eq!(net_model.n_states(), model.n_states());
+        assert_abs_diff_eq!(
+            Array1::from(net_pi.values.clone()),
+            Array1::from(pi.state_values().to_vec()), epsilon = 1e-4);
+        for i in 0..model.n_states() {
+            assert_eq!(net_model.actions[i].len(), model.actions[i].len());
+            let s = model.state(i);
+            let transfer = net_pi.transfer(&net_model, i);
+            let a = transfer[[0, 1]] as i8 - transfer[[1, 0]] as i8;
+            assert_eq!(a, pi.get_action(s.n1, s.n2));
+        }
+    }
+
+    #[test]
+    fn exercise_4_7_agency_is_not_converted() {
+        // Arrange
+        let mut cprobs = RentalAgency::new(5, 3.0, 1.0, 4, 1.0, 2.0, 2);
+        cprobs.variant = RewardVariant::Exercise4_7;
+        // Act
+        let net = RentalNetwork::from_agency(&cprobs);
+        // Assert
+        assert!(matches!(net, Err(ConfigError::Network(_))));
+    }
+}
@@ -0,0 +1,552 @@
+//! One-Way Rentals
+//!
+//! `RentalAgency` treats returns at each location as independent of
+//! rentals. With one-way rentals, every car returned was rented from one of
+//! the two locations. Each car rented at location #i is returned at location
+//! #j with probability routing[i][j], at the end of the day it is rented or
+//! a later day, and is available the next morning. No cars come from
+//! anywhere else, so the fleet size is conserved.
+//!
+//! How long a car is out on rent is a `RentalDuration`, either a fixed
+//! number of days or random. The state is the number of cars on each lot
+//! plus the cars out on rent, counted by age in days and the location
+//! they'll be returned to. Each day, every car out on rent is returned
+//! with a probability that depends on its age, the duration's hazard rate.
+//! Cars older than the last age bucket share the last bucket's hazard rate,
+//! which is exact for geometric durations.
+//!
+//! Cars returned to a full lot are parked at the other location instead.
+//! The fleet fits on the two lots, so the other location always has room.
+//!
+//! Rentals, rewards, and valid actions come from a `RentalAgency` built
+//! without independent returns, so they are the same as for the agency.
+//!
+//! Rental durations are only modeled here. `RentalAgency` and the solvers
+//! built on it, e.g., the `solve` command, keep independent returns. For
+//! the two-location agency with returns that come from the cars out on
+//! rent, use the routing [[1, 0], [0, 1]], so every car comes back to the
+//! lot it was rented from.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use ndarray::Array2;
+use statrs::distribution::{Binomial, Discrete};
+use crate::cars::RentalAgency;
+use crate::config::{ConfigError, OneWayConfig};
+use crate::dist::Distribution;
+use crate::model::ActionModel;
+use crate::solver::State;
+use crate::Convergence;
+
+
+/// Largest number of states `OneWayModel::build` accepts.
+pub const MAX_STATES: usize = 200_000;
+
+
+/// Cars on each lot and out on rent at the start of a day.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OneWayState {
+    /// Number of cars at location #1
+    pub n1: u8,
+    /// Number of cars at location #2
+    pub n2: u8,
+    /// Cars out on rent, by age and the location they'll be returned to.
+    /// Index: days since the car was rented minus one, location. The last
+    /// bucket also holds older cars.
+    pub out: Vec<[u8; 2]>,
+}
+
+impl fmt::Display for OneWayState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(n1: {}, n2: {}, out: {:?})", self.n1, self.n2, self.out)
+    }
+}
+
+
+/// How long cars are out on rent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RentalDuration {
+    /// Probability a car out on rent is returned at the end of the day, by
+    /// days since it was rented. The last applies to all older cars.
+    pub hazard: Vec<f64>,
+}
+
+impl RentalDuration {
+    /// Every car is returned at the end of the day `days` days after the
+    /// day it was rented.
+    pub fn fixed(days: u8) -> RentalDuration {
+        let mut hazard = vec![0.0; days as usize + 1];
+        hazard[days as usize] = 1.0;
+        RentalDuration { hazard }
+    }
+
+    /// Cars are out for a random number of days after the day they were
+    /// rented.
+    ///
+    /// Cars are tracked by age for `buckets` days. The hazard rate at
+    /// age k is P(D = k) / P(D >= k). With no buckets, every car is returned
+    /// the day it is rented.
+    pub fn from_distribution(dist: &Distribution, buckets: u8) -> RentalDuration {
+        if buckets == 0 {
+            return RentalDuration { hazard: vec![1.0] };
+        }
+        let hazard = (0..=buckets as u64)
+            .map(|k| {
+                let survival = if k == 0 { 1.0 } else { 1.0 - dist.cdf(k - 1) };
+                if survival > 1e-12 { (dist.pmf(k) / survival).min(1.0) } else { 1.0 }
+            })
+            .collect();
+        RentalDuration { hazard }
+    }
+
+    /// Number of age buckets for cars out on rent.
+    pub fn buckets(&self) -> usize {
+        self.hazard.len() - 1
+    }
+}
+
+
+/// Precalculated rewards and transition probabilities for one-way rentals.
+pub struct OneWayModel {
+    /// States, in state index order
+    pub states: Vec<OneWayState>,
+    /// Discount rate
+    pub g: f64,
+    /// Models for each valid action. Indexes are state index, action order.
+    pub actions: Vec<Vec<ActionModel>>,
+}
+
+impl OneWayModel {
+    /// Calculate rewards and transition probabilities for every state and
+    /// valid action.
+    ///
+    /// Each next state distribution has a single factor, since returns tie
+    /// the two locations together. Returns an error instead of listing the
+    /// states if there would be more than `MAX_STATES`.
+    pub fn build(
+        agency: &RentalAgency, one_way: &OneWayConfig, duration: &RentalDuration
+    ) -> Result<OneWayModel, ConfigError> {
+        let n_states = count_states(agency, one_way.fleet_size, duration.buckets());
+        if n_states > MAX_STATES as f64 {
+            return Err(ConfigError::OneWay(format!(
+                "model has {n_states:.0} states, more than {MAX_STATES}; \
+                reduce fleet_size or duration_buckets")));
+        }
+        let states = OneWayModel::states(agency, one_way.fleet_size, duration.buckets());
+        let index: HashMap<&OneWayState, usize> = states.iter().enumerate()
+            .map(|(i, s)| (s, i))
+            .collect();
+        let max_rented = agency.max1.max(agency.max2);
+        let splits = [
+            binomial_probs(one_way.routing[0][1], max_rented),
+            binomial_probs(one_way.routing[1][0], max_rented),
+        ];
+        let returns: Vec<Array2<f64>> = duration.hazard.iter()
+            .map(|&h| binomial_probs(h, one_way.fleet_size))
+            .collect();
+        let actions = states.iter()
+            .map(|s| {
+                let lots = State { n1: s.n1, n2: s.n2 };
+                let out = age_out(&returns, s);
+                agency.actions(&lots)
+                    .map(|a| {
+                        let next = next_states(agency, &splits, &returns, &out, s, a);
+                        let factor = next.into_iter()
+                            .map(|(s2, p)| (index[&s2], p))
+                            .collect();
+                        ActionModel {
+                            a, reward: agency.expected_reward(&lots, a), factors: vec![factor]
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(OneWayModel { g: agency.g, states, actions })
+    }
+
+    /// List every state with the fleet on the lots or out on rent.
+    fn states(agency: &RentalAgency, fleet_size: u8, buckets: usize) -> Vec<OneWayState> {
+        let mut states = Vec::new();
+        let mut counts = Vec::new();
+        add_states(agency, 2 + 2 * buckets, fleet_size, &mut counts, &mut states);
+        states
+    }
+
+    /// Number of states.
+    pub fn n_states(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Position of a state in state index order, if it is a valid state.
+    pub fn index(&self, s: &OneWayState) -> Option<usize> {
+        self.states.iter().position(|s2| s2 == s)
+    }
+
+    /// Expected reward plus the discounted expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn backup(&self, am: &ActionModel, v: &[f64]) -> f64 {
+        am.reward + self.g * am.expected_value(v)
+    }
+}
+
+
+/// Number of states with the fleet on the lots or out on rent, without
+/// listing them.
+///
+/// The cars that aren't on the lots can be split between the 2 x `buckets`
+/// counts of cars out in C(out + counts - 1, counts - 1) ways. Counted as a
+/// float, since there can be too many states for any integer type.
+fn count_states(agency: &RentalAgency, fleet_size: u8, buckets: usize) -> f64 {
+    let counts = 2 * buckets;
+    let mut total = 0.0;
+    for n1 in 0..=agency.max1.min(fleet_size) {
+        for n2 in 0..=agency.max2.min(fleet_size - n1) {
+            let out = (fleet_size - n1 - n2) as usize;
+            total += match counts {
+                0 if out == 0 => 1.0,
+                0 => 0.0,
+                _ => (1..counts)
+                    .map(|k| (out + k) as f64 / k as f64)
+                    .product::<f64>(),
+            };
+        }
+    }
+    total
+}
+
+
+/// Recursively list the states where `counts` (n1, n2, then the cars out
+/// at each age and location) add up to the fleet size.
+fn add_states(
+    agency: &RentalAgency, n_counts: usize, remaining: u8, counts: &mut Vec<u8>,
+    states: &mut Vec<OneWayState>
+) {
+    let k = counts.len();
+    if k == n_counts - 1 {
+        counts.push(remaining);
+        let (n1, n2) = (counts[0], counts[1]);
+        if n1 <= agency.max1 && n2 <= agency.max2 {
+            let out = counts[2..].chunks(2).map(|c| [c[0], c[1]]).collect();
+            states.push(OneWayState { n1, n2, out });
+        }
+        counts.pop();
+        return;
+    }
+    let max = match k {
+        0 => agency.max1.min(remaining),
+        1 => agency.max2.min(remaining),
+        _ => remaining,
+    };
+    for n in 0..=max {
+        counts.push(n);
+        add_states(agency, n_counts, remaining - n, counts, states);
+        counts.pop();
+    }
+}
+
+
+/// Probability of b successes in n trials. Indexes: n, b.
+fn binomial_probs(p: f64, max_n: u8) -> Array2<f64> {
+    let dim = max_n as usize + 1;
+    Array2::from_shape_fn((dim, dim), |(n, b)| {
+        Binomial::new(p, n as u64).expect("Invalid probability.").pmf(b as u64)
+    })
+}
+
+
+/// Cars returned to each lot, and cars still out tomorrow morning.
+type Returns = ([u8; 2], Vec<[u8; 2]>);
+
+
+/// Returns from the cars already out on rent at the start of the day.
+///
+/// `returns` are the binomial probabilities of cars returned at each age.
+/// Cars that aren't returned are a day older tomorrow.
+fn age_out(returns: &[Array2<f64>], s: &OneWayState) -> BTreeMap<Returns, f64> {
+    let buckets = s.out.len();
+    let mut outcomes = BTreeMap::from([(([0, 0], vec![[0, 0]; buckets]), 1.0)]);
+    for (k, counts) in s.out.iter().enumerate() {
+        let older = (k + 1).min(buckets - 1);
+        for (j, &n) in counts.iter().enumerate() {
+            outcomes = thin(&outcomes, &returns[k + 1], n, j, older);
+        }
+    }
+    outcomes
+}
+
+
+/// Return each of n cars due at location j with the probabilities in
+/// `returns`, and move the rest into age bucket `older`.
+fn thin(
+    outcomes: &BTreeMap<Returns, f64>, returns: &Array2<f64>, n: u8, j: usize,
+    older: usize
+) -> BTreeMap<Returns, f64> {
+    let mut thinned = BTreeMap::new();
+    for ((returned, out), p) in outcomes {
+        for r in 0..=n {
+            let p_r = returns[[n as usize, r as usize]];
+            if p_r == 0.0 {
+                continue;
+            }
+            let mut returned = *returned;
+            returned[j] += r;
+            let mut out = out.clone();
+            if n > r {
+                out[older][j] += n - r;
+            }
+            *thinned.entry((returned, out)).or_insert(0.0) += p * p_r;
+        }
+    }
+    thinned
+}
+
+
+/// Next state probabilities for a state and action.
+///
+/// `splits` are the probabilities that cars rented at location #1 are
+/// returned at location #2, and the other way around. `out` is the result
+/// of `age_out` for the state.
+fn next_states(
+    agency: &RentalAgency, splits: &[Array2<f64>; 2], returns: &[Array2<f64>],
+    out: &BTreeMap<Returns, f64>, s: &OneWayState, a: i8
+) -> BTreeMap<OneWayState, f64> {
+    let m1 = (s.n1 as i8 - a) as u8;
+    let m2 = (s.n2 as i8 + a) as u8;
+    let mut next = BTreeMap::new();
+    for x1 in 0..=m1 {
+        for x2 in 0..=m2 {
+            let p_x = match agency.rent_joint {
+                Some(_) => agency.joint_rent_prob(m1, m2, x1, x2),
+                None => agency.x1[[m1 as usize, x1 as usize]]
+                    * agency.x2[[m2 as usize, x2 as usize]],
+            };
+            if p_x == 0.0 {
+                continue;
+            }
+            for b1 in 0..=x1 {
+                for b2 in 0..=x2 {
+                    let p = p_x * splits[0][[x1 as usize, b1 as usize]]
+                        * splits[1][[x2 as usize, b2 as usize]];
+                    if p == 0.0 {
+                        continue;
+                    }
+                    // Today's rentals are returned tonight or start aging.
+                    let rented = [x1 - b1 + b2, b1 + x2 - b2];
+                    let mut today = BTreeMap::from([(
+                        ([0, 0], vec![[0, 0]; s.out.len()]), p
+                    )]);
+                    for (j, &n) in rented.iter().enumerate() {
+                        today = thin(&today, &returns[0], n, j, 0);
+                    }
+                    for ((returned, aged), p_today) in &today {
+                        for ((old_returned, old_aged), p_old) in out {
+                            let mut lots = [
+                                m1 - x1 + returned[0] + old_returned[0],
+                                m2 - x2 + returned[1] + old_returned[1],
+                            ];
+                            park(&mut lots, [agency.max1, agency.max2]);
+                            let out = aged.iter().zip(old_aged)
+                                .map(|(c, d)| [c[0] + d[0], c[1] + d[1]])
+                                .collect();
+                            let s2 = OneWayState { n1: lots[0], n2: lots[1], out };
+                            *next.entry(s2).or_insert(0.0) += p_today * p_old;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    next
+}
+
+
+/// Park cars that don't fit on a lot at the other location.
+fn park(lots: &mut [u8; 2], max: [u8; 2]) {
+    if lots[0] > max[0] {
+        lots[1] += lots[0] - max[0];
+        lots[0] = max[0];
+    } else if lots[1] > max[1] {
+        lots[0] += lots[1] - max[1];
+        lots[1] = max[1];
+    }
+}
+
+
+/// Action chosen for each one-way state.
+pub struct OneWayPolicy {
+    /// Position of the chosen action in each state's action list
+    pub actions: Vec<usize>,
+    /// State values, in state index order
+    pub values: Vec<f64>,
+}
+
+impl OneWayPolicy {
+    /// Number of cars moved from location #1 to location #2 in a state.
+    pub fn action(&self, model: &OneWayModel, i: usize) -> i8 {
+        model.actions[i][self.actions[i]].a
+    }
+
+    /// Show each state's action and value in the terminal.
+    pub fn show(&self, model: &OneWayModel) {
+        println!("{:>4} {:>4} | {:>20} | {:>10} | action", "n1", "n2", "out", "value");
+        for (i, s) in model.states.iter().enumerate() {
+            println!("{:>4} {:>4} | {:>20} | {:>10.2} | {}",
+                s.n1, s.n2, format!("{:?}", s.out), self.values[i], self.action(model, i));
+        }
+    }
+}
+
+
+/// Find the optimal one-way policy with value iteration.
+///
+/// Ties keep the first valid action, as in `table_value_iteration`.
+pub fn one_way_value_iteration(
+    model: &OneWayModel, theta: f64
+) -> (OneWayPolicy, Convergence) {
+    let (actions, values, conv) =
+        crate::table_value_iteration(&model.actions, model.g, theta);
+    (OneWayPolicy { actions, values }, conv)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    fn agency() -> RentalAgency {
+        let none = || Distribution::Empirical(vec![1.0]);
+        RentalAgency::with_distributions(
+            5, Distribution::poisson(2.0), none(),
+            5, Distribution::poisson(1.0), none(), 2)
+    }
+
+    fn one_way(routing: [[f64; 2]; 2]) -> OneWayConfig {
+        OneWayConfig {
+            fleet_size: 6, routing, return_delay: 0, duration: None, duration_buckets: None
+        }
+    }
+
+    #[test_case(RentalDuration::fixed(0), 0; "Returned the same day")]
+    #[test_case(RentalDuration::fixed(2), 2; "Returned after two days")]
+    #[test_case(RentalDuration::from_distribution(
+        &Distribution::Geometric { p: 0.4 }, 1), 1; "Geometric duration")]
+    fn fleet_is_conserved(duration: RentalDuration, buckets: usize) {
+        // Arrange
+        let agency = agency();
+        // Act
+        let model = OneWayModel::build(&agency, &one_way([[0.7, 0.3], [0.4, 0.6]]), &duration)
+            .unwrap();
+        // Assert
+        for (s, ams) in model.states.iter().zip(&model.actions) {
+            let out: u8 = s.out.iter().flatten().sum();
+            assert_eq!(s.n1 + s.n2 + out, 6);
+            assert_eq!(s.out.len(), buckets);
+            for am in ams {
+                let total: f64 = am.next().iter().map(|(_, p)| p).sum();
+                assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn state_count_matches_listed_states_and_is_limited() {
+        // Arrange
+        let agency = agency();
+        let duration = RentalDuration::fixed(2);
+        // Act
+        let count = count_states(&agency, 6, duration.buckets());
+        let listed = OneWayModel::states(&agency, 6, duration.buckets()).len();
+        // Assert
+        assert_eq!(count, listed as f64);
+        assert_eq!(count_states(&agency, 6, 0), 5.0);
+        let mut big = one_way([[1.0, 0.0], [0.0, 1.0]]);
+        big.fleet_size = 10;
+        let result = OneWayModel::build(&agency, &big, &RentalDuration::fixed(14));
+        assert!(matches!(result, Err(ConfigError::OneWay(_))));
+    }
+
+    #[test]
+    fn hazard_rates_match_durations() {
+        // Act
+        let fixed = RentalDuration::fixed(2);
+        let geometric = RentalDuration::from_distribution(
+            &Distribution::Geometric { p: 0.25 }, 3);
+        let empirical = RentalDuration::from_distribution(
+            &Distribution::Empirical(vec![0.5, 0.25, 0.25]), 2);
+        // Assert
+        assert_eq!(fixed.hazard, vec![0.0, 0.0, 1.0]);
+        for h in &geometric.hazard {
+            assert_abs_diff_eq!(*h, 0.25, epsilon = 1e-12);
+        }
+        assert_eq!(empirical.hazard, vec![0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn round_trips_return_to_the_same_lot() {
+        // Arrange
+        let agency = agency();
+        // Act
+        let model = OneWayModel::build(
+            &agency, &one_way([[1.0, 0.0], [0.0, 1.0]]), &RentalDuration::fixed(0)).unwrap();
+        // Assert
+        assert_eq!(model.n_states(), 5);
+        let s = OneWayState { n1: 4, n2: 2, out: Vec::new() };
+        let i = model.index(&s).unwrap();
+        let am = model.actions[i].iter().find(|am| am.a == 2).unwrap();
+        let j = model.index(&OneWayState { n1: 2, n2: 4, out: Vec::new() }).unwrap();
+        let next = am.next();
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].0, j);
+        assert_abs_diff_eq!(next[0].1, 1.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn cars_out_return_at_the_hazard_rate() {
+        // Arrange
+        let agency = agency();
+        let duration = RentalDuration::from_distribution(&Distribution::Geometric { p: 0.4 }, 1);
+        let model = OneWayModel::build(&agency, &one_way([[1.0, 0.0], [0.0, 1.0]]), &duration)
+            .unwrap();
+        // Empty lots, so nothing is rented today.
+        let s = OneWayState { n1: 0, n2: 0, out: vec![[4, 2]] };
+        // Act
+        let next = model.actions[model.index(&s).unwrap()][0].next();
+        // Assert
+        assert_eq!(next.len(), 15);
+        let s2 = OneWayState { n1: 1, n2: 2, out: vec![[3, 0]] };
+        let j = model.index(&s2).unwrap();
+        let p = next.iter().find(|(i, _)| *i == j).unwrap().1;
+        assert_abs_diff_eq!(p, 4.0 * 0.4 * 0.6_f64.powi(3) * 0.4 * 0.4, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn full_lot_parks_cars_at_the_other_location() {
+        // Arrange
+        let mut lots = [4, 6];
+        // Act
+        park(&mut lots, [5, 5]);
+        // Assert
+        assert_eq!(lots, [5, 5]);
+    }
+
+    #[test]
+    fn policy_moves_cars_against_the_flow() {
+        // Arrange
+        let agency = agency();
+        let model = OneWayModel::build(
+            &agency, &one_way([[0.2, 0.8], [0.1, 0.9]]), &RentalDuration::fixed(1)).unwrap();
+        // Act
+        let (pi, conv) = one_way_value_iteration(&model, 1e-6);
+        // Assert
+        assert!(conv.sweeps > 1);
+        let s = OneWayState { n1: 0, n2: 5, out: vec![[0, 1]] };
+        assert!(pi.action(&model, model.index(&s).unwrap()) < 0);
+        for (i, ams) in model.actions.iter().enumerate() {
+            let am = &ams[pi.actions[i]];
+            assert_abs_diff_eq!(model.backup(am, &pi.values), pi.values[i], epsilon = 1e-4);
+        }
+    }
+}
@@ -6,7 +6,10 @@
 
 #![allow(unused)]
 
+use std::error::Error;
+use std::path::Path;
 use crate::cars::RentalAgency;
+use crate::model::TransitionModel;
 
 
 /// Mapping of states to action.
@@ -15,6 +18,10 @@ use crate::cars::RentalAgency;
 /// of each state-action combination. The value is the expected value of
 /// the sum of all subsequent rewards, assuming we follow the policy.
 /// 
+/// The `value` field contains our current estimate of the value of each
+/// state when following the policy. It is kept consistent with
+/// `action_value`, i.e., V(n1, n2) = Q(n1, n2, policy(n1, n2)).
+/// 
 /// The `policy` field is a mapping of states to actions. The indices are
 /// the number of cars at location 1 and location 2, and the array value
 /// is an integer representing the number of cars to move from loc #1 to
@@ -28,6 +35,8 @@ pub struct Policy {
     pub max_move: u8,
     /// Indexes are n1, n2, a + max_move
     pub action_value: ndarray::Array3<f64>,
+    /// State values. Indexes are n1, n2
+    pub value: ndarray::Array2<f64>,
     /// Indexes are n1, n2
     pub policy: ndarray::Array2<i8>
 }
@@ -41,27 +50,135 @@ impl Policy {
             ((max1 + 1) as usize, (max2 + 1) as usize, total_moves as usize);
         let action_value = 
             ndarray::Array3::<f64>::zeros(dimensions);
+        let value =
+            ndarray::Array2::<f64>::zeros(
+                ((max1 + 1) as usize, (max2 + 1) as usize));
         let policy_array =
             ndarray::Array2::<i8>::zeros(
                 ((max1 + 1) as usize, (max2 + 1) as usize));
-        let policy = Policy {
-            max1, max2, max_move, action_value, policy: policy_array
-        };
-        policy
+        Policy {
+            max1, max2, max_move, action_value, value, policy: policy_array
+        }
     }
 
     pub fn build_from_agency(agency: &RentalAgency) -> Policy {
         Policy::new(agency.max1, agency.max2, agency.max_move)
     }
 
+    pub fn build_from_model(model: &TransitionModel) -> Policy {
+        Policy::new(model.max1, model.max2, model.max_move)
+    }
+
     pub fn get_value(&self, n1: u8, n2: u8, a: i8) -> f64 {
         let a_idx = (a + self.max_move as i8) as usize;
         self.action_value[[n1 as usize, n2 as usize, a_idx]]
     }
 
+    /// Set the value of a state-action combination.
+    ///
+    /// Also updates the state value if `a` is the policy's action.
     pub fn set_value(&mut self, n1: u8, n2: u8, a: i8, v: f64) {
         let a_idx = (a + self.max_move as i8) as usize;
         self.action_value[[n1 as usize, n2 as usize, a_idx]] = v;
+        if self.get_action(n1, n2) == a {
+            self.value[[n1 as usize, n2 as usize]] = v;
+        }
+    }
+
+    pub fn get_state_value(&self, n1: u8, n2: u8) -> f64 {
+        self.value[[n1 as usize, n2 as usize]]
+    }
+
+    /// State values in `StateIterator` order.
+    pub fn state_values(&self) -> &[f64] {
+        self.value.as_slice().expect("Value table is not contiguous.")
+    }
+
+    pub fn get_action(&self, n1: u8, n2: u8) -> i8 {
+        self.policy[[n1 as usize, n2 as usize]]
+    }
+
+    /// Set the policy's action for a state.
+    ///
+    /// The state value becomes the value of the new action.
+    pub fn set_action(&mut self, n1: u8, n2: u8, a: i8) {
+        self.policy[[n1 as usize, n2 as usize]] = a;
+        self.value[[n1 as usize, n2 as usize]] = self.get_value(n1, n2, a);
+    }
+
+    /// Read the policy's actions from a CSV file.
+    ///
+    /// The file has one row for each number of cars at location #1 and one
+    /// column for each number of cars at location #2, with no header row.
+    pub fn read_actions_csv(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)?;
+        let mut n1 = 0;
+        for record in rdr.records() {
+            let record = record?;
+            if n1 > self.max1 as usize || record.len() != self.max2 as usize + 1 {
+                return Err("Policy table doesn't match lot sizes.".into());
+            }
+            for (n2, field) in record.iter().enumerate() {
+                let a: i8 = field.trim().parse()?;
+                if a.unsigned_abs() > self.max_move {
+                    return Err(format!("Policy action out of range: {a}").into());
+                }
+                self.set_action(n1 as u8, n2 as u8, a);
+            }
+            n1 += 1;
+        }
+        if n1 != self.max1 as usize + 1 {
+            return Err("Policy table doesn't match lot sizes.".into());
+        }
+        Ok(())
+    }
+
+    /// Write the policy's actions to a CSV file, in the format read by
+    /// `read_actions_csv`.
+    pub fn write_actions_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for row in self.policy.rows() {
+            wtr.write_record(row.iter().map(|a| a.to_string()))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Show the policy table in the terminal.
+    ///
+    /// Rows are the number of cars at location #1 and columns are the number
+    /// of cars at location #2.
+    pub fn show_policy(&self) {
+        print!("  n1 \\ n2 |");
+        for n2 in 0..=self.max2 {
+            print!("{:4}", n2);
+        }
+        println!();
+        for (n1, row) in self.policy.rows().into_iter().enumerate() {
+            print!("{n1:>9} |");
+            for a in row.iter() {
+                print!("{:4}", a);
+            }
+            println!();
+        }
+    }
+
+    /// Show the state values in the terminal.
+    pub fn show_values(&self) {
+        print!("  n1 \\ n2 |");
+        for n2 in 0..=self.max2 {
+            print!("{:9}", n2);
+        }
+        println!();
+        for (n1, row) in self.value.rows().into_iter().enumerate() {
+            print!("{n1:>9} |");
+            for v in row.iter() {
+                print!("{:9.2}", v);
+            }
+            println!();
+        }
     }
 }
 
@@ -86,6 +203,51 @@ mod tests {
         assert_eq!(pdims.0, 5);
         assert_eq!(pdims.1, 5);
         assert_eq!(dpolicy.policy[[0, 0]], 0);
+        assert_eq!(dpolicy.value.dim(), (5, 5));
+    }
+
+    #[test]
+    fn state_value_follows_policy_action() {
+        // Arrange
+        let mut pi = Policy::new(4, 4, 2);
+        // Act
+        pi.set_value(1, 2, 0, 5.0);
+        pi.set_value(1, 2, 1, 7.0);
+        // Assert
+        assert_eq!(pi.get_state_value(1, 2), 5.0);
+        pi.set_action(1, 2, 1);
+        assert_eq!(pi.get_state_value(1, 2), 7.0);
+        pi.set_value(1, 2, -1, 9.0);
+        assert_eq!(pi.get_state_value(1, 2), 7.0);
+    }
+
+    #[test]
+    fn actions_csv_round_trip() {
+        // Arrange
+        let mut pi = Policy::new(3, 2, 1);
+        pi.set_action(1, 0, 1);
+        pi.set_action(2, 2, -1);
+        let path = std::env::temp_dir().join("rustcar_policy_round_trip.csv");
+        // Act
+        pi.write_actions_csv(&path).unwrap();
+        let mut read = Policy::new(3, 2, 1);
+        read.read_actions_csv(&path).unwrap();
+        // Assert
+        assert_eq!(read.policy, pi.policy);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn actions_csv_rejects_out_of_range_action() {
+        // Arrange
+        let path = std::env::temp_dir().join("rustcar_policy_out_of_range.csv");
+        std::fs::write(&path, "0,0\n0,9\n").unwrap();
+        let mut pi = Policy::new(1, 1, 1);
+        // Act
+        let result = pi.read_actions_csv(&path);
+        // Assert
+        assert!(result.unwrap_err().to_string().contains("out of range"));
+        std::fs::remove_file(&path).unwrap();
     }
 
 }
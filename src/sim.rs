@@ -0,0 +1,142 @@
+//! Day Simulator
+//!
+//! The dynamic programming solvers use the rental and return probability
+//! tables directly. Model-free learning methods only see sampled days, so
+//! the `DaySimulator` draws each day's demand and returns from the
+//! `RentalAgency`'s distributions and reports the reward and next state, as
+//! a real agency would see them.
+//!
+//! A simulated day follows the same rules as the transition model: cars are
+//! moved overnight, customers rent cars until the lot is empty, returned
+//! cars are available the next morning, and cars that don't fit on the lot
+//! are removed from the problem. Averaged over many days, rewards and next
+//! states match `RentalAgency::expected_reward` and the next-morning
+//! probabilities.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::cars::RentalAgency;
+use crate::solver::State;
+
+
+/// Samples days at a rental agency with a seeded random number generator.
+pub struct DaySimulator<'a> {
+    /// Agency whose distributions, prices, and costs are simulated
+    pub agency: &'a RentalAgency,
+    rng: StdRng,
+}
+
+impl<'a> DaySimulator<'a> {
+    /// Create a simulator. The same seed always gives the same days.
+    pub fn new(agency: &'a RentalAgency, seed: u64) -> DaySimulator<'a> {
+        DaySimulator { agency, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Random number generator, for choosing actions and starting states
+    /// from the same seeded sequence.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// State chosen uniformly at random.
+    pub fn random_state(&mut self) -> State {
+        State {
+            n1: self.rng.gen_range(0..=self.agency.max1),
+            n2: self.rng.gen_range(0..=self.agency.max2),
+        }
+    }
+
+    /// Simulate one day after taking action `a` in state `s`.
+    ///
+    /// Returns the reward and the next state. The lost-sales penalty is
+    /// charged for each customer actually turned away.
+    pub fn step(&mut self, s: &State, a: i8) -> (f64, State) {
+        let agency = self.agency;
+        let m1 = (s.n1 as i8 - a) as u64;
+        let m2 = (s.n2 as i8 + a) as u64;
+        let (d1, d2) = match &agency.rent_joint_dist {
+            Some(joint) => joint.sample(&mut self.rng),
+            None => (
+                agency.rent_dist1.sample(&mut self.rng),
+                agency.rent_dist2.sample(&mut self.rng),
+            ),
+        };
+        let (x1, x2) = (d1.min(m1), d2.min(m2));
+        let y1 = agency.return_dist1.sample(&mut self.rng);
+        let y2 = agency.return_dist2.sample(&mut self.rng);
+        let lost = (d1 - x1) + (d2 - x2);
+        let reward = x1 as f64 * agency.price1 + x2 as f64 * agency.price2
+            - agency.lost_sale_penalty * lost as f64
+            - agency.action_cost(s, a);
+        let next = State {
+            n1: (m1 - x1 + y1).min(agency.max1 as u64) as u8,
+            n2: (m2 - x2 + y2).min(agency.max2 as u64) as u8,
+        };
+        (reward, next)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::JointConfig;
+    use crate::dist::{Distribution, JointDistribution};
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    #[test_case(false; "Independent demand")]
+    #[test_case(true; "Correlated demand")]
+    fn simulated_days_match_the_model(correlated: bool) {
+        // Arrange
+        let mut agency = if correlated {
+            let joint = JointConfig::BivariatePoisson {
+                mean1: 3.0, mean2: 2.0, common_mean: 1.0
+            };
+            RentalAgency::with_joint_rentals(
+                6, &JointDistribution::from_config("rent_joint", &joint).unwrap(),
+                Distribution::poisson(2.0), 6, Distribution::poisson(3.0), 2)
+        } else {
+            RentalAgency::new(6, 3.0, 2.0, 6, 2.0, 3.0, 2)
+        };
+        agency.lost_sale_penalty = 3.0;
+        let s = State { n1: 4, n2: 2 };
+        let mut sim = DaySimulator::new(&agency, 11);
+        let days = 400_000;
+        // Act
+        let mut total = 0.0;
+        let mut next1 = [0.0; 7];
+        for _ in 0..days {
+            let (r, next) = sim.step(&s, 1);
+            total += r;
+            next1[next.n1 as usize] += 1.0 / days as f64;
+        }
+        // Assert
+        assert_abs_diff_eq!(total / days as f64, agency.expected_reward(&s, 1),
+            epsilon = 0.08);
+        // Next-morning probabilities at location #1, with 3 cars after moving
+        let t1: Vec<f64> = match &agency.t_joint {
+            Some(t) => (0..7)
+                .map(|n| t.row(3 * 7 + 3).slice(ndarray::s![n * 7..n * 7 + 7]).sum())
+                .collect(),
+            None => agency.t1.row(3).to_vec(),
+        };
+        for (p, q) in next1.iter().zip(t1) {
+            assert_abs_diff_eq!(p, &q, epsilon = 0.01);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_days() {
+        // Arrange
+        let agency = RentalAgency::new(5, 3.0, 2.0, 5, 2.0, 3.0, 2);
+        let mut sim1 = DaySimulator::new(&agency, 3);
+        let mut sim2 = DaySimulator::new(&agency, 3);
+        let s = State { n1: 2, n2: 3 };
+        // Act
+        let days1: Vec<(f64, State)> = (0..20).map(|_| sim1.step(&s, 0)).collect();
+        let days2: Vec<(f64, State)> = (0..20).map(|_| sim2.step(&s, 0)).collect();
+        // Assert
+        assert_eq!(days1, days2);
+    }
+}
@@ -43,7 +43,7 @@ impl Iterator for StateIterator {
             self.n1 += 1;
         }
 
-        return Some(state);
+        Some(state)
     }
 }
 
@@ -66,8 +66,7 @@ impl fmt::Display for Outcome {
 
 impl Outcome {
     pub fn new(x1: i32, x2: i32) -> Outcome {
-        let outcome = Outcome { x1, x2,  y1: 0, y2: 0 };
-        outcome
+        Outcome { x1, x2,  y1: 0, y2: 0 }
     }
 
     fn is_nonnegative(&self) -> bool {
@@ -173,7 +172,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, 0, 0);
         // Assert
-        assert_eq!(outcomes.len(), 1 as usize);
+        assert_eq!(outcomes.len(), 1);
         assert_eq!(outcomes[0], Outcome {x1: 0, y1: 0, x2: 0, y2: 0});
     }
 
@@ -188,7 +187,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 4 as usize);
+        assert_eq!(outcomes.len(), 4);
         assert_eq!(outcomes[0], Outcome {x1: 0, y1: 0, x2: 3, y2: 3});
         assert_eq!(outcomes[outcomes.len() - 1], Outcome {x1: 3, y1: 3, x2: 0, y2: 0});
         for outcome in outcomes {
@@ -206,7 +205,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 3 as usize);
+        assert_eq!(outcomes.len(), 3);
         assert_eq!(outcomes[0], Outcome {x1: 0, y1: 0, x2: 2, y2: 2});
         assert_eq!(outcomes[outcomes.len() - 1], Outcome {x1: 2, y1: 2, x2: 0, y2: 0});
         for outcome in outcomes {
@@ -224,7 +223,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 0 as usize);
+        assert_eq!(outcomes.len(), 0);
     }
 
     #[test]
@@ -237,7 +236,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 2 as usize);
+        assert_eq!(outcomes.len(), 2);
         assert_eq!(outcomes[0], Outcome {x1: 1, y1: 1, x2: 2, y2: 2});
         assert_eq!(outcomes[outcomes.len() - 1], Outcome {x1: 2, y1: 2, x2: 1, y2: 1});
         for outcome in outcomes {
@@ -254,7 +253,7 @@ mod tests {
         let a: i8 = 1;
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
-        assert_eq!(outcomes.len(), 2 as usize);
+        assert_eq!(outcomes.len(), 2);
         assert_eq!(outcomes[0], Outcome {x1: 0, y1: 1, x2: 3, y2: 2});
         assert_eq!(outcomes[outcomes.len() - 1], Outcome {x1: 1, y1: 2, x2: 2, y2: 1});
         for outcome in outcomes {
@@ -272,7 +271,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 1 as usize);
+        assert_eq!(outcomes.len(), 1);
         assert_eq!(outcomes[0], Outcome {x1: 3, y1: 1, x2: 0, y2: 2});
         for outcome in outcomes {
             assert!(check_outcome(&s1, &s2, &outcome, xt, a));
@@ -289,7 +288,7 @@ mod tests {
         // Act
         let outcomes = Outcome::solve(&s1, &s2, xt, a);
         // Assert
-        assert_eq!(outcomes.len(), 0 as usize);
+        assert_eq!(outcomes.len(), 0);
     }
 
 /*
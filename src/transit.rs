@@ -0,0 +1,375 @@
+//! Transfers With Lead Time
+//!
+//! `RentalAgency` assumes cars moved overnight are on the receiving lot the
+//! next morning. When the lots are far apart, moved cars can spend one or
+//! more days on the road. With `days` days in transit, cars moved tonight
+//! miss `days` days of rentals at both locations and arrive at the end of
+//! the last one, so they're on the receiving lot the morning after.
+//!
+//! The state is the number of cars on each lot plus the cars in transit,
+//! one count per day still on the road. Only one direction is moved each
+//! night, so each count is signed like an action, i.e., positive counts are
+//! headed to location #2. Moves are limited by the cars on the sending lot,
+//! but not by the room on the receiving lot, since that changes before the
+//! cars arrive. Cars that arrive at a full lot are removed from the
+//! problem, like returns to a full lot.
+//!
+//! States are indexed lot-major, index = lots * n_transit + transit, where
+//! lots is the state's `TransitionModel` index and transit treats the
+//! counts as the digits of a base (2 * max_move + 1) number, earliest
+//! arrival first. With no days in transit, the model is the same as the
+//! `TransitionModel`.
+
+use std::fmt;
+use crate::cars::RentalAgency;
+use crate::config::ConfigError;
+use crate::model::{ActionModel, TransitionModel};
+use crate::solver::State;
+use crate::Convergence;
+
+
+/// Largest number of states `TransitModel::build` accepts.
+pub const MAX_STATES: usize = 200_000;
+
+
+/// Cars on each lot and in transit at the start of a night.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitState {
+    /// Number of cars at location #1
+    pub n1: u8,
+    /// Number of cars at location #2
+    pub n2: u8,
+    /// Cars in transit, positive if headed to location #2. Index: days
+    /// until the cars arrive minus one.
+    pub transit: Vec<i8>,
+}
+
+impl fmt::Display for TransitState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(n1: {}, n2: {}, transit: {:?})", self.n1, self.n2, self.transit)
+    }
+}
+
+
+/// Precalculated rewards and transition probabilities for transfers with
+/// lead time.
+pub struct TransitModel {
+    /// Maximum number of cars that can be stored at location #1
+    pub max1: u8,
+    /// Maximum number of cars that can be stored at location #2
+    pub max2: u8,
+    /// Maximum number of cars that can be moved between locations
+    pub max_move: u8,
+    /// Days moved cars spend in transit
+    pub days: u8,
+    /// Discount rate
+    pub g: f64,
+    /// Models for each valid action. Indexes are state index, action order.
+    pub actions: Vec<Vec<ActionModel>>,
+}
+
+impl TransitModel {
+    /// Calculate rewards and transition probabilities for every state and
+    /// valid action.
+    ///
+    /// The cars in transit tomorrow are known, so they're added to the
+    /// first factor's index offsets. Cars that arrive tonight are added to
+    /// the receiving lot's next-morning inventory. Returns an error instead
+    /// of listing the states if there would be more than `MAX_STATES`.
+    pub fn build(agency: &RentalAgency, days: u8) -> Result<TransitModel, ConfigError> {
+        let n_states = count_states(agency, days);
+        if n_states > MAX_STATES as f64 {
+            return Err(ConfigError::TransferDays(format!(
+                "model has {n_states:.0} states, more than {MAX_STATES}; \
+                reduce transfer_days, max_move or the lot sizes")));
+        }
+        let mut model = TransitModel {
+            max1: agency.max1, max2: agency.max2, max_move: agency.max_move,
+            days, g: agency.g, actions: Vec::new()
+        };
+        if days == 0 {
+            model.actions = TransitionModel::build(agency).actions;
+            return Ok(model);
+        }
+        model.actions = (0..model.n_lot_states() * model.n_transit())
+            .map(|i| {
+                let s = model.state(i);
+                let lots = State { n1: s.n1, n2: s.n2 };
+                let max_move = agency.max_move as i8;
+                (-max_move.min(s.n2 as i8)..=max_move.min(s.n1 as i8))
+                    .map(|a| ActionModel {
+                        a,
+                        reward: agency.expected_transit_reward(&lots, a),
+                        factors: model.factors(agency, &s, a),
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(model)
+    }
+
+    /// Next state distribution for a state and action, with cars in
+    /// transit.
+    fn factors(
+        &self, agency: &RentalAgency, s: &TransitState, a: i8
+    ) -> Vec<Vec<(usize, f64)>> {
+        let m1 = (s.n1 as i8 - a.max(0)) as usize;
+        let m2 = (s.n2 as i8 + a.min(0)) as usize;
+        let arriving = s.transit[0];
+        let arrive1 = |n: usize| (n + (-arriving).max(0) as usize).min(self.max1 as usize);
+        let arrive2 = |n: usize| (n + arriving.max(0) as usize).min(self.max2 as usize);
+        let mut transit = s.transit[1..].to_vec();
+        transit.push(a);
+        let transit_index = self.transit_index(&transit);
+        let width = self.max2 as usize + 1;
+        let n_transit = self.n_transit();
+        if let Some(t) = &agency.t_joint {
+            let mut probs = vec![0.0; self.n_lot_states()];
+            for (j, p) in t.row(m1 * width + m2).iter().enumerate() {
+                probs[arrive1(j / width) * width + arrive2(j % width)] += p;
+            }
+            let factor = sparse(&probs, |lots| lots * n_transit + transit_index);
+            return vec![factor];
+        }
+        let mut probs1 = vec![0.0; self.max1 as usize + 1];
+        for (n, p) in agency.t1.row(m1).iter().enumerate() {
+            probs1[arrive1(n)] += p;
+        }
+        let mut probs2 = vec![0.0; width];
+        for (n, p) in agency.t2.row(m2).iter().enumerate() {
+            probs2[arrive2(n)] += p;
+        }
+        vec![
+            sparse(&probs1, |n| n * width * n_transit + transit_index),
+            sparse(&probs2, |n| n * n_transit),
+        ]
+    }
+
+    /// Number of (n1, n2) states.
+    fn n_lot_states(&self) -> usize {
+        (self.max1 as usize + 1) * (self.max2 as usize + 1)
+    }
+
+    /// Number of ways to have cars in transit.
+    fn n_transit(&self) -> usize {
+        (2 * self.max_move as usize + 1).pow(self.days as u32)
+    }
+
+    /// Number of states.
+    pub fn n_states(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Position of the cars in transit among the `n_transit` combinations.
+    fn transit_index(&self, transit: &[i8]) -> usize {
+        let base = 2 * self.max_move as usize + 1;
+        transit.iter()
+            .fold(0, |i, &cars| i * base + (cars + self.max_move as i8) as usize)
+    }
+
+    /// Position of a state in state index order.
+    pub fn index(&self, s: &TransitState) -> usize {
+        let lots = s.n1 as usize * (self.max2 as usize + 1) + s.n2 as usize;
+        lots * self.n_transit() + self.transit_index(&s.transit)
+    }
+
+    /// State at a position in state index order.
+    pub fn state(&self, i: usize) -> TransitState {
+        let width = self.max2 as usize + 1;
+        let base = 2 * self.max_move as usize + 1;
+        let (lots, mut t) = (i / self.n_transit(), i % self.n_transit());
+        let mut transit = vec![0; self.days as usize];
+        for cars in transit.iter_mut().rev() {
+            *cars = (t % base) as i8 - self.max_move as i8;
+            t /= base;
+        }
+        TransitState { n1: (lots / width) as u8, n2: (lots % width) as u8, transit }
+    }
+
+    /// Expected reward plus the discounted expected value of the next state.
+    ///
+    /// `v` contains the state values in state index order.
+    pub fn backup(&self, am: &ActionModel, v: &[f64]) -> f64 {
+        am.reward + self.g * am.expected_value(v)
+    }
+}
+
+
+/// Number of states with `days` days in transit, as a float so large
+/// counts don't overflow.
+fn count_states(agency: &RentalAgency, days: u8) -> f64 {
+    let lots = (agency.max1 as f64 + 1.0) * (agency.max2 as f64 + 1.0);
+    lots * (2.0 * agency.max_move as f64 + 1.0).powi(days as i32)
+}
+
+
+/// (index offset, probability) pairs for the nonzero probabilities.
+fn sparse(probs: &[f64], offset: impl Fn(usize) -> usize) -> Vec<(usize, f64)> {
+    probs.iter().enumerate()
+        .filter(|(_, p)| **p > 0.0)
+        .map(|(n, p)| (offset(n), *p))
+        .collect()
+}
+
+
+/// Action chosen for each state with cars in transit.
+pub struct TransitPolicy {
+    /// Position of the chosen action in each state's action list
+    pub actions: Vec<usize>,
+    /// State values, in state index order
+    pub values: Vec<f64>,
+}
+
+impl TransitPolicy {
+    /// Number of cars moved from location #1 to location #2 in a state.
+    pub fn action(&self, model: &TransitModel, i: usize) -> i8 {
+        model.actions[i][self.actions[i]].a
+    }
+
+    /// Show each state's action and value in the terminal.
+    pub fn show(&self, model: &TransitModel) {
+        println!("{:>4} {:>4} | {:>12} | {:>10} | action", "n1", "n2", "transit", "value");
+        for i in 0..model.n_states() {
+            let s = model.state(i);
+            println!("{:>4} {:>4} | {:>12} | {:>10.2} | {}",
+                s.n1, s.n2, format!("{:?}", s.transit), self.values[i],
+                self.action(model, i));
+        }
+    }
+}
+
+
+/// Find the optimal policy for transfers with lead time with value
+/// iteration.
+///
+/// Ties keep the first valid action, as in `table_value_iteration`.
+pub fn transit_value_iteration(
+    model: &TransitModel, theta: f64
+) -> (TransitPolicy, Convergence) {
+    let (actions, values, conv) =
+        crate::table_value_iteration(&model.actions, model.g, theta);
+    (TransitPolicy { actions, values }, conv)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::JointConfig;
+    use crate::dist::{Distribution, JointDistribution};
+    use approx::assert_abs_diff_eq;
+    use test_case::test_case;
+
+    fn agency() -> RentalAgency {
+        RentalAgency::new(5, 2.0, 1.0, 5, 1.0, 2.0, 2)
+    }
+
+    #[test]
+    fn no_transit_matches_transition_model() {
+        // Arrange
+        let agency = agency();
+        // Act
+        let model = TransitModel::build(&agency, 0).unwrap();
+        let (pi, _) = transit_value_iteration(&model, 1e-6);
+        let (expected, _) = crate::value_iteration(&TransitionModel::build(&agency), 1e-6);
+        // Assert
+        assert_eq!(model.n_states(), 36);
+        for i in 0..model.n_states() {
+            let s = model.state(i);
+            assert_eq!(s.transit, Vec::<i8>::new());
+            assert_eq!(pi.action(&model, i), expected.get_action(s.n1, s.n2));
+            assert_abs_diff_eq!(pi.values[i], expected.get_state_value(s.n1, s.n2),
+                epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn too_many_states_is_an_error() {
+        // Arrange
+        let agency = agency();
+        // Act
+        let model = TransitModel::build(&agency, 30);
+        // Assert
+        assert!(matches!(model, Err(ConfigError::TransferDays(_))));
+        assert_eq!(count_states(&agency, 2), 900.0);
+    }
+
+    #[test_case(1; "One day in transit")]
+    #[test_case(2; "Two days in transit")]
+    fn states_are_indexed_in_order(days: u8) {
+        // Arrange
+        let agency = agency();
+        // Act
+        let model = TransitModel::build(&agency, days).unwrap();
+        // Assert
+        assert_eq!(model.n_states(), 36 * 5usize.pow(days as u32));
+        for (i, ams) in model.actions.iter().enumerate() {
+            let s = model.state(i);
+            assert_eq!(model.index(&s), i);
+            assert_eq!(s.transit.len(), days as usize);
+            for am in ams {
+                let total: f64 = am.next().iter().map(|(_, p)| p).sum();
+                assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test_case(3, 1, vec![2, 0], 1, 2, 3, vec![0, 1]; "Arrival at location 2")]
+    #[test_case(3, 1, vec![-2, 2], -1, 5, 0, vec![2, -1]; "Arrival at location 1")]
+    #[test_case(2, 4, vec![2, 0], 0, 2, 5, vec![0, 0]; "Arrival at a full lot")]
+    fn moved_cars_arrive_after_transit(
+        n1: u8, n2: u8, transit: Vec<i8>, a: i8, next1: u8, next2: u8,
+        next_transit: Vec<i8>
+    ) {
+        // Arrange
+        let none = || Distribution::Empirical(vec![1.0]);
+        let agency = RentalAgency::with_distributions(5, none(), none(), 5, none(), none(), 2);
+        let model = TransitModel::build(&agency, 2).unwrap();
+        let s = TransitState { n1, n2, transit };
+        // Act
+        let am = model.actions[model.index(&s)].iter().find(|am| am.a == a).unwrap();
+        // Assert
+        let s2 = TransitState { n1: next1, n2: next2, transit: next_transit };
+        assert_eq!(am.next(), vec![(model.index(&s2), 1.0)]);
+        assert_eq!(am.reward, -agency.move_cost * a.abs() as f64);
+    }
+
+    #[test]
+    fn correlated_demand_has_one_factor() {
+        // Arrange
+        let joint = JointConfig::BivariatePoisson { mean1: 2.0, mean2: 1.0, common_mean: 0.5 };
+        let agency = RentalAgency::with_joint_rentals(
+            5, &JointDistribution::from_config("rent_joint", &joint).unwrap(),
+            Distribution::poisson(1.0), 5, Distribution::poisson(2.0), 2);
+        // Act
+        let model = TransitModel::build(&agency, 1).unwrap();
+        // Assert
+        for ams in &model.actions {
+            for am in ams {
+                assert_eq!(am.factors.len(), 1);
+                let total: f64 = am.next().iter()
+                    .inspect(|(j, _)| assert_eq!(model.state(*j).transit, vec![am.a]))
+                    .map(|(_, p)| p)
+                    .sum();
+                assert_abs_diff_eq!(total, 1.0, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn transit_changes_the_policy() {
+        // Arrange
+        let agency = RentalAgency::new(8, 3.0, 1.0, 8, 1.0, 3.0, 3);
+        let model = TransitModel::build(&agency, 1).unwrap();
+        // Act
+        let (pi, conv) = transit_value_iteration(&model, 1e-6);
+        let (instant, _) = crate::value_iteration(&TransitionModel::build(&agency), 1e-6);
+        // Assert
+        assert!(conv.sweeps > 1);
+        let differs = (0..model.n_states())
+            .map(|i| (model.state(i), pi.action(&model, i)))
+            .filter(|(s, _)| s.transit == vec![0])
+            .any(|(s, a)| a != instant.get_action(s.n1, s.n2));
+        assert!(differs);
+    }
+}